@@ -1,90 +1,140 @@
 use crate::dmem::ir::Data;
-use crate::imem::ir::unresolved::{Inst, InstKind, Value};
-
-pub fn check(datas: &[Data], insts: &[Inst]) -> anyhow::Result<()> {
-    check_label_exists(datas, insts)?;
-    check_label_usage(insts)?;
-    check_reg_range(insts)?;
-    check_value_range(insts)?;
-
-    Ok(())
+use crate::error::{Error, ErrorKind, Errors, Span};
+use crate::imem::ir::unresolved::{Inst, InstKind};
+use crate::linkage::Linkage;
+
+// 各検査は見つけた違反をすべて errors に積む
+// (data_end, inst_end は各セクションの終わりを指すラベル)
+pub fn check(
+    datas: &[Data],
+    data_end: &[(String, Span)],
+    insts: &[Inst],
+    inst_end: &[(String, Span)],
+    linkage: &Linkage,
+    errors: &mut Errors,
+) {
+    let data_labels = datas
+        .iter()
+        .flat_map(|data| &data.labels)
+        .chain(data_end)
+        .map(|(label, _)| label.as_str())
+        .collect::<Vec<&str>>();
+    let inst_labels = insts
+        .iter()
+        .flat_map(|inst| &inst.labels)
+        .chain(inst_end)
+        .map(|(label, _)| label.as_str())
+        .collect::<Vec<&str>>();
+
+    check_label_exists(datas, insts, &data_labels, &inst_labels, linkage, errors);
+    check_global_defined(&data_labels, &inst_labels, linkage, errors);
+    check_label_usage(insts, errors);
+    check_reg_range(insts, errors);
 }
 
 // 宣言されていないラベルを呼び出ししていたらエラー
-fn check_label_exists(datas: &[Data], insts: &[Inst]) -> anyhow::Result<()> {
-    let data_labels = datas
+// (.extern で宣言したものは他のオブジェクトで定義されている)
+fn check_label_exists(
+    datas: &[Data],
+    insts: &[Inst],
+    data_labels: &[&str],
+    inst_labels: &[&str],
+    linkage: &Linkage,
+    errors: &mut Errors,
+) {
+    // 式とその位置 (と擬似命令の展開結果)
+    let data_exprs = datas
         .iter()
-        .filter(|data| data.label.is_some())
-        .map(|data| data.label.clone().unwrap())
-        .collect::<Vec<String>>();
+        .filter_map(|data| Some((data.command.expr()?, data.span, None)));
+    let inst_exprs = insts.iter().filter_map(|inst| {
+        let span = inst.arg_spans.imm.unwrap_or(inst.span);
+        Some((inst.kind.expr()?, span, inst.note()))
+    });
+
+    for (expr, span, note) in data_exprs.chain(inst_exprs) {
+        for (prefix, label) in expr.labels() {
+            let labels = if prefix == '$' {
+                data_labels
+            } else {
+                inst_labels
+            };
+            if !labels.contains(&label) && !linkage.is_extern(prefix, label) {
+                errors.push(
+                    Error::new(
+                        ErrorKind::LabelNotFound(format!("{}{}", prefix, label)),
+                        span,
+                    )
+                    .with_note(note.clone()),
+                );
+            }
+        }
+    }
+}
 
-    let inst_labels = insts
-        .iter()
-        .filter(|inst| inst.label.is_some())
-        .map(|inst| inst.label.clone().unwrap())
-        .collect::<Vec<String>>();
+// .global で宣言したラベルはこのファイルで定義されていなければならない
+fn check_global_defined(
+    data_labels: &[&str],
+    inst_labels: &[&str],
+    linkage: &Linkage,
+    errors: &mut Errors,
+) {
+    for decl in &linkage.globals {
+        let labels = if decl.prefix == '$' {
+            data_labels
+        } else {
+            inst_labels
+        };
+        if !labels.contains(&decl.name.as_str()) {
+            let label = format!("{}{}", decl.prefix, decl.name);
+            errors.push(Error::new(ErrorKind::GlobalNotDefined(label), decl.span));
+        }
+    }
+}
 
+fn check_label_usage(insts: &[Inst], errors: &mut Errors) {
     for inst in insts {
-        // val に 呼び出してる val のリストを入れてる
+        #[rustfmt::skip]
         let val = match &inst.kind {
-            InstKind::Addi { val, .. } => val,
-            InstKind::Subi { val, .. } => val,
             InstKind::Beq { val, .. } => val,
             InstKind::Bne { val, .. } => val,
             InstKind::Blt { val, .. } => val,
             InstKind::Ble { val, .. } => val,
             _ => continue,
         };
-
-        match val {
-            Value::DataLabel(label) => {
-                if !data_labels.contains(label) {
-                    return Err(anyhow::anyhow!("label {} is not found", label));
-                }
-            }
-            Value::InstLabel(label) => {
-                if !inst_labels.contains(label) {
-                    return Err(anyhow::anyhow!("label {} is not found", label));
-                }
+        for (prefix, label) in val.labels() {
+            if prefix == '$' {
+                errors.push(
+                    Error::new(
+                        ErrorKind::DataLabelInBranch(label.to_string()),
+                        inst.arg_spans.imm.unwrap_or(inst.span),
+                    )
+                    .with_note(inst.note()),
+                );
             }
-            _ => {}
         }
     }
-    Ok(())
 }
 
-fn check_label_usage(insts: &[Inst]) -> anyhow::Result<()> {
-    for inst in insts {
-        #[rustfmt::skip]
-        let is_collect_usage = match &inst.kind {
-            InstKind::Beq { val, .. } => !matches!(val, Value::DataLabel(_)),
-            InstKind::Bne { val, .. } => !matches!(val, Value::DataLabel(_)),
-            InstKind::Blt { val, .. } => !matches!(val, Value::DataLabel(_)),
-            InstKind::Ble { val, .. } => !matches!(val, Value::DataLabel(_)),
-            _ => continue,
-        };
-        if !is_collect_usage {
-            return Err(anyhow::anyhow!(
-                "Datalabel is not permitted in branch instruction {:?}",
-                inst
-            ));
-        }
-    }
-    Ok(())
-}
-
-fn check_reg_range(insts: &[Inst]) -> anyhow::Result<()> {
+fn check_reg_range(insts: &[Inst], errors: &mut Errors) {
     // I形式：rd: 0-31, rs1: 0-7
     // R/B/S形式: rd/rs1/rs2: 0-31
 
-    let check_i_type = |rd: &u8, rs1: &u8| -> bool { *rd <= 31 && *rs1 <= 7 };
-    let check_s_type = |rs1: &u8, rs2: &u8| -> bool { *rs1 <= 7 && *rs2 <= 31 };
-    let check_other_type =
-        |rd: &u8, rs1: &u8, rs2: &u8| -> bool { *rd <= 31 && *rs1 <= 31 && *rs2 <= 31 };
+    // 範囲外のレジスタを (フィールド名, レジスタ番号, 上限) で返す
+    let invalid = |regs: &[(&'static str, u8, u8)]| -> Vec<(&'static str, u8, u8)> {
+        regs.iter()
+            .copied()
+            .filter(|&(_, reg, max)| reg > max)
+            .collect()
+    };
+    let check_i_type = |rd: &u8, rs1: &u8| invalid(&[("rd", *rd, 31), ("rs1", *rs1, 7)]);
+    let check_s_type = |rs1: &u8, rs2: &u8| invalid(&[("rs1", *rs1, 7), ("rs2", *rs2, 31)]);
+    let check_other_type = |rd: &u8, rs1: &u8, rs2: &u8| {
+        invalid(&[("rd", *rd, 31), ("rs1", *rs1, 31), ("rs2", *rs2, 31)])
+    };
 
     for inst in insts {
         #[rustfmt::skip]
-        let is_correct_reg = match &inst.kind {
+        let invalid_regs = match &inst.kind {
             // I-type
             InstKind::Addi { rd, rs1, .. } => check_i_type(rd, rs1),
             InstKind::Subi { rd, rs1, .. } => check_i_type(rd, rs1),
@@ -125,44 +175,14 @@ fn check_reg_range(insts: &[Inst]) -> anyhow::Result<()> {
             InstKind::Blt { rd, rs1, rs2, .. } => check_other_type(rd, rs1, rs2),
             InstKind::Ble { rd, rs1, rs2, .. } => check_other_type(rd, rs1, rs2),
         };
-        if !is_correct_reg {
-            return Err(anyhow::anyhow!("Invalid register usage: {:?}", inst));
-        }
-    }
-    Ok(())
-}
-
-fn check_value_range(insts: &[Inst]) -> anyhow::Result<()> {
-    let is_imm_u32 = |val: &Value| -> bool {
-        if let Value::Imm(imm) = val {
-            *imm >= u32::MIN as i64 && *imm <= u32::MAX as i64
-        } else {
-            true
-        }
-    };
-
-    let is_imm_i25 = |val: &Value| -> bool {
-        if let Value::Imm(imm) = val {
-            *imm >= (-(1 << 24) as i64) || *imm < ((1 << 24) as i64)
-        } else {
-            true
-        }
-    };
-
-    for inst in insts {
-        #[rustfmt::skip]
-        let is_correct_imm = match &inst.kind {
-            InstKind::Addi { val, .. } => is_imm_u32(val),
-            InstKind::Subi { val, .. } => is_imm_u32(val),
-            InstKind::Beq { val, .. } => is_imm_i25(val),
-            InstKind::Bne { val, .. } => is_imm_i25(val),
-            InstKind::Blt { val, .. } => is_imm_i25(val),
-            InstKind::Ble { val, .. } => is_imm_i25(val),
-            _ => continue,
-        };
-        if !is_correct_imm {
-            return Err(anyhow::anyhow!("Imm is overflow: {:?}", inst));
+        for (field, reg, max) in invalid_regs {
+            errors.push(
+                Error::new(
+                    ErrorKind::InvalidRegister { field, reg, max },
+                    inst.arg_spans.get(field).unwrap_or(inst.span),
+                )
+                .with_note(inst.note()),
+            );
         }
     }
-    Ok(())
 }
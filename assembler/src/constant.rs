@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use crate::error::{Error, ErrorKind, Errors, Span};
+use crate::lex::{Line, Token, TokenKind};
+use crate::source::SourceMap;
+use crate::syntax::Parser;
+
+// 名前 -> (値, 定義した位置)
+type Consts = HashMap<String, (i64, Span)>;
+
+// .equ NAME, value の行を取り除き, 以降の NAME を数値に置き換える
+// (include を展開した順で定義より後の行で使える. include したファイルで定義したものは include の行より後でも使える)
+//
+// .equ UART_TX, 0
+//     out r0[UART_TX] = r5
+pub fn substitute(lines: &[Line], sources: &SourceMap, errors: &mut Errors) -> Vec<Line> {
+    let mut consts = Consts::new();
+    // 定義中のマクロの引数 (置き換えない)
+    let mut params = Vec::new();
+
+    let mut result = Vec::new();
+    for line in lines {
+        let directive = match &line.tokens[0].kind {
+            TokenKind::Ident(ident) => ident.as_str(),
+            _ => "",
+        };
+        match directive {
+            ".macro" => {
+                params = line
+                    .tokens
+                    .iter()
+                    .skip(2)
+                    .filter_map(|token| match &token.kind {
+                        TokenKind::Ident(param) => Some(param.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                result.push(line.clone());
+                continue;
+            }
+            ".endm" => params.clear(),
+            _ => {}
+        }
+
+        // 命令名・コマンド名 (とその前のラベル), .equ で定義する名前は置き換えない
+        let skip = match line.tokens[0].kind {
+            TokenKind::DataLabel(_) | TokenKind::InstLabel(_) => 2,
+            _ if directive == ".equ" => 2,
+            _ => 1,
+        };
+        let tokens = line
+            .tokens
+            .iter()
+            .enumerate()
+            .map(|(idx, token)| match &token.kind {
+                TokenKind::Ident(name) if idx >= skip && !params.contains(name) => {
+                    match consts.get(name) {
+                        Some(&(value, _)) => Token {
+                            kind: TokenKind::Num(value),
+                            span: token.span,
+                        },
+                        None => token.clone(),
+                    }
+                }
+                _ => token.clone(),
+            })
+            .collect();
+        let line = Line {
+            tokens,
+            eol: line.eol,
+            expansion: line.expansion.clone(),
+        };
+
+        if directive != ".equ" {
+            result.push(line);
+            continue;
+        }
+        match parse_equ(&line) {
+            Ok((name, span, value)) => match consts.get(&name) {
+                // 同じファイルを何度 include しても, 同じ定義はエラーにしない
+                Some(&(_, first)) if same_position(sources, first, span) => {}
+                Some(_) => errors.push(Error::new(ErrorKind::ConstRedefined(name), span)),
+                None => {
+                    consts.insert(name, (value, span));
+                }
+            },
+            Err(err) => errors.push(err),
+        }
+    }
+    result
+}
+
+// 同じパスのファイルの同じ位置か
+fn same_position(sources: &SourceMap, a: Span, b: Span) -> bool {
+    let path = |span: Span| sources.get(span.file).map(|file| &file.path);
+    (a.line, a.col) == (b.line, b.col) && path(a).is_some() && path(a) == path(b)
+}
+
+// .equ NAME, expr
+fn parse_equ(line: &Line) -> Result<(String, Span, i64), Error> {
+    let mut parser = Parser::new(line);
+    parser.expect_ident()?;
+    let (name, span) = parser.expect_ident()?;
+    parser.expect_punct(",")?;
+    let (expr, expr_span) = parser.expect_expr()?;
+    parser.expect_end()?;
+
+    // ラベルはまだアドレスが決まっていないので使えない
+    let value = expr
+        .eval(&HashMap::new(), &HashMap::new())
+        .map_err(|kind| Error::new(kind, expr_span))?;
+    Ok((name, span, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use crate::error::Errors;
+    use crate::source::SourceMap;
+    use crate::{assemble, assemble_image, Options};
+
+    fn assemble_files(files: &[(&str, &str)]) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let loader = files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect::<HashMap<_, _>>();
+        let mut sources = SourceMap::new();
+        let path = Path::new(files[0].0);
+        assemble_image(path, &loader, &mut sources, &Options::default())
+    }
+
+    fn error_kinds(err: anyhow::Error) -> Vec<String> {
+        let errors = err.downcast::<Errors>().unwrap();
+        errors.iter().map(|err| err.kind.to_string()).collect()
+    }
+
+    #[test]
+    fn offsets() {
+        let with_consts = assemble(
+            ".equ PORT, 4
+.equ BUF, 0x100
+.equ WORD, 4
+.text
+    lw r1 = r2[BUF + WORD]
+    sw r2[-WORD] = r1
+    in r3 = r0[PORT]
+    out r0[PORT + 1] = r3
+    addi r4 = r0, BUF * 2
+",
+            6,
+        )
+        .unwrap();
+        let literal = assemble(
+            ".text
+    lw r1 = r2[0x104]
+    sw r2[-4] = r1
+    in r3 = r0[4]
+    out r0[5] = r3
+    addi r4 = r0, 0x200
+",
+            6,
+        )
+        .unwrap();
+        assert_eq!(with_consts, literal);
+    }
+
+    #[test]
+    fn redefined() {
+        let err = assemble(".equ A, 1\n.equ A, 1\n.text\n", 6).unwrap_err();
+        assert_eq!(error_kinds(err), ["Constant A is already defined"]);
+
+        let err = assemble_files(&[
+            ("main.asm", ".equ A, 1\ninclude \"defs.asm\"\n.text\n"),
+            ("defs.asm", ".equ A, 2\n"),
+        ])
+        .unwrap_err();
+        assert_eq!(error_kinds(err), ["Constant A is already defined"]);
+    }
+
+    #[test]
+    fn include_scope() {
+        let defs = ("lib/defs.asm", ".equ UART_TX, 4\n");
+        let expected = assemble_files(&[(
+            "main.asm",
+            ".text\n    out r0[4] = r5\n    out r0[4] = r6\n",
+        )])
+        .unwrap();
+
+        // include したファイルの定数は include の行より後で使える (同じファイルを何度 include してもよい)
+        let image = assemble_files(&[
+            (
+                "main.asm",
+                ".text\ninclude \"lib/defs.asm\"\ninclude \"lib/out.asm\"\n    out r0[UART_TX] = r6\n",
+            ),
+            ("lib/out.asm", "include \"defs.asm\"\n    out r0[UART_TX] = r5\n"),
+            defs,
+        ])
+        .unwrap();
+        assert_eq!(image, expected);
+
+        let err = assemble_files(&[
+            (
+                "main.asm",
+                ".text\n    out r0[UART_TX] = r5\ninclude \"lib/defs.asm\"\n",
+            ),
+            defs,
+        ])
+        .unwrap_err();
+        assert_eq!(error_kinds(err), ["Constant UART_TX is not defined"]);
+    }
+
+    #[test]
+    fn macro_params() {
+        // マクロの引数と同じ名前の定数は, マクロの中では引数を指す
+        let with_consts = assemble(
+            ".equ n, 100
+.text
+.macro set n
+    li r1 = n
+.endm
+    set 7
+    li r2 = n
+",
+            6,
+        )
+        .unwrap();
+        let literal = assemble(".text\n    li r1 = 7\n    li r2 = 100\n", 6).unwrap();
+        assert_eq!(with_consts, literal);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::BTreeSet;
+
+use crate::convert::{encode, parse_hex};
+use crate::error::{Error, ErrorKind, Errors, Span};
+use crate::format::{ihex, srec};
+use crate::imem::ir::resolved::Inst;
+use crate::Options;
+
+// inst.hex (Intel HEX, S-record でもよい) を imem::parse が受け付ける書式に戻す
+// (分岐先には @L_<アドレス> のラベルを付ける)
+pub fn disassemble(hex: &str, chunk_size: usize) -> anyhow::Result<String> {
+    let options = Options {
+        chunk_size,
+        ..Default::default()
+    };
+    disassemble_with(hex, &options)
+}
+
+// 末尾の options.fill の byte はメモリの大きさまで埋めた埋め草として捨てる
+pub fn disassemble_with(hex: &str, options: &Options) -> anyhow::Result<String> {
+    let insts = decode_hex(hex, options.chunk_size, options.fill)?;
+
+    let targets = insts
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, inst)| branch_target(idx, inst, insts.len()))
+        .collect::<BTreeSet<_>>();
+
+    let mut result = String::from("===\n");
+    for (idx, inst) in insts.iter().enumerate() {
+        if targets.contains(&idx) {
+            result += &format!("{}\n", label_of(idx));
+        }
+        let line = inst.to_string();
+        let target = branch_target(idx, inst, insts.len());
+        let line = match (target, line.rsplit_once("-> ")) {
+            (Some(target), Some((head, _))) => format!("{}-> {}", head, label_of(target)),
+            _ => line,
+        };
+        result += &format!("    {}\n", line);
+    }
+    Ok(result)
+}
+
+// 48bit の命令語を命令に戻す
+// (使われないビットが立っているなど, 再アセンブルで同じ命令語にならないものは None)
+#[rustfmt::skip]
+pub fn decode(word: u64) -> Option<Inst> {
+    let bits = |lo: u32, len: u32| ((word >> lo) & ((1 << len) - 1)) as u32;
+
+    let opcode = bits(0, 5);
+    let funct3 = bits(5, 3);
+    let rd = bits(8, 5) as u8;
+
+    // R/B 形式
+    let rs1 = bits(13, 5) as u8;
+    let rs2 = bits(18, 5) as u8;
+    let b_imm = ((bits(23, 25) << 7) as i32) >> 7;
+
+    // I/S 形式
+    let i_rs1 = bits(13, 3) as u8;
+    let i_imm = bits(16, 32);
+    let s_rs2 = rd;
+
+    let inst = match (opcode, funct3) {
+        (0b00001, 0b001) => Inst::Add { rd, rs1, rs2 },
+        (0b00001, 0b010) => Inst::Sub { rd, rs1, rs2 },
+
+        (0b00010, 0b001) => Inst::Addi { rd, rs1: i_rs1, imm: i_imm },
+        (0b00010, 0b010) => Inst::Subi { rd, rs1: i_rs1, imm: i_imm },
+
+        (0b00011, 0b000) => Inst::Beq { rd, rs1, rs2, imm: b_imm },
+        (0b00011, 0b001) => Inst::Bne { rd, rs1, rs2, imm: b_imm },
+        (0b00011, 0b010) => Inst::Blt { rd, rs1, rs2, imm: b_imm },
+        (0b00011, 0b011) => Inst::Ble { rd, rs1, rs2, imm: b_imm },
+        (0b00011, 0b100) => Inst::Jal { rd, rs1: i_rs1, imm: i_imm as i32 },
+
+        (0b00100, 0b000) => Inst::Lw { rd, rs1: i_rs1, imm: i_imm as i32 },
+        (0b00100, 0b001) => Inst::Lh { rd, rs1: i_rs1, imm: i_imm as i32 },
+        (0b00100, 0b010) => Inst::Lb { rd, rs1: i_rs1, imm: i_imm as i32 },
+        (0b00100, 0b011) => Inst::Lhu { rd, rs1: i_rs1, imm: i_imm as i32 },
+        (0b00100, 0b100) => Inst::Lbu { rd, rs1: i_rs1, imm: i_imm as i32 },
+
+        (0b00101, 0b000) => Inst::Sw { rs1: i_rs1, rs2: s_rs2, imm: i_imm as i32 },
+        (0b00101, 0b001) => Inst::Sh { rs1: i_rs1, rs2: s_rs2, imm: i_imm as i32 },
+        (0b00101, 0b010) => Inst::Sb { rs1: i_rs1, rs2: s_rs2, imm: i_imm as i32 },
+        (0b00101, 0b011) => Inst::Isb { rs1: i_rs1, rs2: s_rs2, imm: i_imm as i32 },
+
+        (0b00110, 0b000) => Inst::In { rd, rs1: i_rs1, imm: i_imm as i32 },
+        (0b00110, 0b001) => Inst::Out { rs1: i_rs1, rs2: s_rs2, imm: i_imm as i32 },
+
+        (0b00111, 0b000) => Inst::And { rd, rs1, rs2 },
+        (0b00111, 0b001) => Inst::Or { rd, rs1, rs2 },
+        (0b00111, 0b010) => Inst::Xor { rd, rs1, rs2 },
+        (0b00111, 0b011) => Inst::Srl { rd, rs1, rs2 },
+        (0b00111, 0b100) => Inst::Sra { rd, rs1, rs2 },
+        (0b00111, 0b101) => Inst::Sll { rd, rs1, rs2 },
+
+        (0b01000, 0b000) => Inst::Andi { rd, rs1: i_rs1, imm: i_imm },
+        (0b01000, 0b001) => Inst::Ori { rd, rs1: i_rs1, imm: i_imm },
+        (0b01000, 0b010) => Inst::Xori { rd, rs1: i_rs1, imm: i_imm },
+        (0b01000, 0b011) => Inst::Srli { rd, rs1: i_rs1, imm: i_imm },
+        (0b01000, 0b100) => Inst::Srai { rd, rs1: i_rs1, imm: i_imm },
+        (0b01000, 0b101) => Inst::Slli { rd, rs1: i_rs1, imm: i_imm },
+
+        _ => return None,
+    };
+
+    match encode(&inst) {
+        Ok(encoded) if encoded == word => Some(inst),
+        _ => None,
+    }
+}
+
+// chunk_size ごとに区切られた hex を命令列に戻す
+fn decode_hex(hex: &str, chunk_size: usize, fill: u8) -> Result<Vec<Inst>, Errors> {
+    let mut bytes = parse_bytes(hex, chunk_size)?;
+
+    // 末尾の fill は埋め草として捨てる (最後の命令語の上位 byte が fill と同じこともあるので,
+    // 残りが命令語の境界になるまで戻す. fill だけの命令語は埋め草とみなす)
+    let fills = bytes
+        .iter()
+        .rev()
+        .take_while(|(byte, _)| *byte == fill)
+        .count();
+    let len = (bytes.len() - fills).next_multiple_of(6);
+    if len > bytes.len() {
+        let span = bytes.last().map(|(_, span)| *span).unwrap_or_default();
+        return Err(Error::new(ErrorKind::IncompleteInst, span).into());
+    }
+    bytes.truncate(len);
+
+    let mut insts = Vec::new();
+    let mut errors = Errors::new(None);
+    for word_bytes in bytes.chunks(6) {
+        let word = word_bytes
+            .iter()
+            .rev()
+            .fold(0, |word, (byte, _)| (word << 8) | *byte as u64);
+        match decode(word) {
+            Some(inst) => insts.push(inst),
+            None => errors.push(Error::new(ErrorKind::UnknownInst(word), word_bytes[0].1)),
+        }
+    }
+    errors.into_result()?;
+
+    Ok(insts)
+}
+
+// 最初の行の先頭の文字で形式を判別する
+fn parse_bytes(hex: &str, chunk_size: usize) -> Result<Vec<(u8, Span)>, Errors> {
+    let first = hex.lines().map(str::trim).find(|line| !line.is_empty());
+    match first.and_then(|line| line.chars().next()) {
+        Some(':') => Ok(ihex::parse(hex)?.1),
+        Some('S') => Ok(srec::parse(hex)?.1),
+        _ => parse_hex(hex, chunk_size),
+    }
+}
+
+// 命令列の中を指す分岐命令の飛び先 (命令の番号)
+fn branch_target(idx: usize, inst: &Inst, len: usize) -> Option<usize> {
+    let imm = match *inst {
+        Inst::Beq { imm, .. } => imm,
+        Inst::Bne { imm, .. } => imm,
+        Inst::Blt { imm, .. } => imm,
+        Inst::Ble { imm, .. } => imm,
+        _ => return None,
+    };
+    let target = (idx * 6) as i64 + imm as i64;
+    if target < 0 || target % 6 != 0 || (target / 6) as usize >= len {
+        return None;
+    }
+    Some((target / 6) as usize)
+}
+
+fn label_of(idx: usize) -> String {
+    format!("@L_{:04X}", idx * 6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_map::{MemoryMap, Region};
+    use crate::{assemble_with, Options};
+
+    const PROGRAM: &str = "===
+@main
+    addi r1 = r0, 10
+@loop
+    subi r1 = r1, 1
+    bne r0, (r1, r0) -> @loop
+    j @main
+";
+
+    // すべての命令の種類と, 命令列の外への分岐
+    const ALL_INSTS: &str = "===
+@main
+    add r1 = r2, r3
+    sub r4 = r5, r6
+    addi r1 = r2, 100
+    subi r3 = r4, -1
+@loop
+    beq r0, (r1, r2) -> @loop
+    bne r1, (r3, r4) -> @main
+    blt r2, (r5, r6) -> @end
+    ble r3, (r7, r8) -> @end
+    jal r1, r2[12]
+    lw r1 = r2[4]
+    lh r1 = r2[-2]
+    lb r1 = r2[1]
+    lhu r1 = r2[2]
+    lbu r1 = r2[3]
+    sw r2[0] = r3
+    sh r2[2] = r3
+    sb r2[1] = r3
+    isb r2[6] = r3
+    in r1 = r0[0x10]
+    out r0[0x11] = r5
+    and r1 = r2, r3
+    or r1 = r2, r3
+    xor r1 = r2, r3
+    srl r1 = r2, r3
+    sra r1 = r2, r3
+    sll r1 = r2, r3
+    andi r1 = r2, 0xFF
+    ori r1 = r2, 0xF0
+    xori r1 = r2, -1
+    srli r1 = r2, 3
+    srai r1 = r2, 4
+    slli r1 = r2, 5
+@end
+    beq r0, (r0, r0) -> 0x600
+";
+
+    #[test]
+    fn round_trip() {
+        for chunk_size in [1, 4, 6, 8] {
+            let (_, inst) = crate::assemble(ALL_INSTS, chunk_size).unwrap();
+            let program = disassemble(&inst, chunk_size).unwrap();
+            let (_, again) = crate::assemble(&program, chunk_size).unwrap();
+            assert_eq!(again, inst, "chunk_size: {}\n{}", chunk_size, program);
+        }
+    }
+
+    #[test]
+    fn padded_image() {
+        for fill in [0x00, 0xFF] {
+            let options = Options {
+                chunk_size: 4,
+                memory_map: MemoryMap {
+                    imem: Region {
+                        base: 0,
+                        size: Some(64),
+                    },
+                    ..Default::default()
+                },
+                fill,
+                ..Default::default()
+            };
+            let (_, inst) = assemble_with(PROGRAM, &options).unwrap();
+            assert_eq!(inst.lines().count(), 16);
+
+            let program = disassemble_with(&inst, &options).unwrap();
+            assert_eq!(program.lines().filter(|line| line.starts_with("    ")).count(), 4);
+            assert_eq!(assemble_with(&program, &options).unwrap().1, inst);
+        }
+    }
+}
@@ -1,20 +1,36 @@
+use crate::error::Span;
+use crate::expr::Expr;
+
 #[derive(Debug)]
 pub struct Data {
-    pub label: Option<String>,
+    // このデータを指すラベル (続けて書いたものはすべて) とその位置
+    pub labels: Vec<(String, Span)>,
     pub command: Command,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub enum Command {
-    Byte1(u8),
-    Byte2(u16),
-    Byte4(u32),
-    Byte6(u64),
+    Byte1(Expr),
+    Byte2(Expr),
+    Byte4(Expr),
+    Byte6(Expr),
     Char(char),
     String(String),
 }
 
 impl Command {
+    pub fn expr(&self) -> Option<&Expr> {
+        match self {
+            Command::Byte1(expr) => Some(expr),
+            Command::Byte2(expr) => Some(expr),
+            Command::Byte4(expr) => Some(expr),
+            Command::Byte6(expr) => Some(expr),
+            Command::Char(_) | Command::String(_) => None,
+        }
+    }
+
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         match self {
             Command::Byte1(_) => 1,
@@ -22,7 +38,7 @@ impl Command {
             Command::Byte4(_) => 4,
             Command::Byte6(_) => 6,
             Command::Char(_) => 1,
-            Command::String(s) => s.as_bytes().len() + 1,
+            Command::String(s) => s.len() + 1,
         }
     }
 }
@@ -1,140 +1,137 @@
 use crate::dmem::ir::{Command, Data};
+use crate::error::{Error, ErrorKind, Errors, Span};
+use crate::expr::Expr;
+use crate::lex::{Line, Token, TokenKind};
+use crate::syntax::Parser;
 
-pub fn parse(lines: &[&str]) -> anyhow::Result<Vec<Data>> {
-    let lines = lines
-        .into_iter()
-        .map(|line| line.trim())
-        .map(|line| line.split("//").collect::<Vec<_>>()[0])
-        .filter(|line| !line.is_empty())
-        .collect::<Vec<&str>>();
-
+// program
+// 1: $label
+// 2: byte1 0x01, 0x02\n
+// 3: byte4 $label + 4\n
+// 4: string "Hello, world!"\n
+// 5: ...
+// 続けて書いたラベルはすべて次のデータを指す
+// 後にデータのないラベルは (データ部の終わりを指すものとして) 別に返す
+pub fn parse(lines: &[Line], errors: &mut Errors) -> (Vec<Data>, Vec<(String, Span)>) {
     let mut data = Vec::new();
-    let mut label = None;
+    let mut labels = Vec::new();
     for line in lines {
+        let mut parser = Parser::new(line);
+
         // label
-        if line.starts_with("$") {
-            label = Some(line[1..].to_string());
-        } else {
-            let mut line_data = parse_line(line)?;
-            if label.is_some() {
-                line_data[0].label = label.take();
+        if let Some(Token {
+            kind: TokenKind::DataLabel(name),
+            span,
+        }) = parser.peek()
+        {
+            labels.push((name.clone(), *span));
+            parser.bump();
+            if parser.is_end() {
+                continue;
             }
-            data.extend(line_data);
+        }
+
+        // エラーの行は読み飛ばして次の行から再開する
+        match parse_line(&mut parser) {
+            Ok(mut line_data) => {
+                if let Some(first) = line_data.first_mut() {
+                    first.labels = std::mem::take(&mut labels);
+                }
+                data.extend(line_data);
+            }
+            Err(err) => errors.push(err),
         }
     }
 
-    Ok(data)
+    (data, labels)
 }
 
-fn parse_line(line: &str) -> anyhow::Result<Vec<Data>> {
-    let splitted_line = line.split_whitespace().collect::<Vec<_>>();
-    let command = splitted_line[0].trim();
-    let args = line.replacen(command, "", 1);
+// command arg, arg, ...
+fn parse_line(parser: &mut Parser) -> Result<Vec<Data>, Error> {
+    let (command, command_span) = parser.expect_ident()?;
 
     let mut data = Vec::new();
-    for arg in args.trim().split(",") {
-        let arg = arg.trim();
-        let inst_command = match command {
-            "byte1" => Command::Byte1(parse_u8(arg)?),
-            "byte2" => Command::Byte2(parse_u16(arg)?),
-            "byte4" => Command::Byte4(parse_u32(arg)?),
-            "byte6" => Command::Byte6(parse_u48(arg)?),
-            "char" => Command::Char(parse_char(arg)?),
-            "string" => Command::String(parse_string(arg)?),
-            _ => return Err(anyhow::anyhow!("Invalid command: {}", command)),
-        };
+    loop {
+        let start = parser.span();
+        let inst_command = match command.as_str() {
+            "byte1" => parse_bytes(parser).map(Command::Byte1),
+            "byte2" => parse_bytes(parser).map(Command::Byte2),
+            "byte4" => parse_bytes(parser).map(Command::Byte4),
+            "byte6" => parse_bytes(parser).map(Command::Byte6),
+            "char" => parse_char(parser).map(Command::Char),
+            "string" => parse_string(parser).map(Command::String),
+            _ => Err(Error::new(
+                ErrorKind::InvalidCommand(command.clone()),
+                command_span,
+            )),
+        }?;
         data.push(Data {
-            label: None,
+            labels: Vec::new(),
             command: inst_command,
+            span: start.to(parser.prev_span()),
         });
-    }
 
-    Ok(data)
-}
-
-fn parse_u8(num: &str) -> anyhow::Result<u8> {
-    let mut num = parse_num_with_radix(num)?;
-    if !(i8::MIN as i64 <= num && num <= u8::MAX as i64) {
-        return Err(anyhow::anyhow!("Invalid value: {}", num));
-    }
-    if num < 0 {
-        num = (num + (u8::MAX) as i64 + 1) | 0x80;
+        if !parser.eat_punct(",") {
+            break;
+        }
     }
-    Ok(num as u8)
-}
+    parser.expect_end()?;
 
-fn parse_u16(num: &str) -> anyhow::Result<u16> {
-    let mut num = parse_num_with_radix(num)?;
-    if !(i16::MIN as i64 <= num && num <= u16::MAX as i64) {
-        return Err(anyhow::anyhow!("Invalid value: {}", num));
-    }
-    if num < 0 {
-        num = (num + (u16::MAX) as i64 + 1) | 0x8000;
-    }
-    Ok(num as u16)
+    Ok(data)
 }
 
-fn parse_u32(num: &str) -> anyhow::Result<u32> {
-    let mut num = parse_num_with_radix(num)?;
-    if !(i32::MIN as i64 <= num && num <= u32::MAX as i64) {
-        return Err(anyhow::anyhow!("Invalid value: {}", num));
-    }
-    if num < 0 {
-        num = (num + (u32::MAX) as i64 + 1) | 0x80000000;
-    }
-    Ok(num as u32)
+fn parse_bytes(parser: &mut Parser) -> Result<Expr, Error> {
+    let (expr, _) = parser.expect_expr()?;
+    Ok(expr)
 }
 
-fn parse_u48(num: &str) -> anyhow::Result<u64> {
-    let mut num = parse_num_with_radix(num)?;
-    let i48_min = -(1 << 47);
-    let u48_max = (1 << 48) - 1;
-    if !(i48_min <= num && num <= u48_max) {
-        return Err(anyhow::anyhow!("Invalid value: {}", num));
-    }
-    if num < 0 {
-        num = (num + u48_max + 1) | 0x800000000000;
+fn parse_char(parser: &mut Parser) -> Result<char, Error> {
+    match parser.peek() {
+        Some(Token {
+            kind: TokenKind::Char(c),
+            span,
+        }) => {
+            // char は 1 byte なので, U+00FF までの文字だけ
+            if *c as u32 > 0xFF {
+                let literal = format!("{:?} does not fit in a byte", c);
+                return Err(Error::new(ErrorKind::InvalidChar(literal), *span));
+            }
+            parser.bump();
+            Ok(*c)
+        }
+        _ => Err(parser.unexpected("character literal")),
     }
-    Ok(num as u64)
 }
 
-fn parse_num_with_radix(num_s: &str) -> anyhow::Result<i64> {
-    // 0x 0X: 16, 0b 0B: 2, nothing: 10
-    let num = if (num_s.starts_with("0x") || num_s.starts_with("0X")) && num_s.len() > 2 {
-        i64::from_str_radix(&num_s[2..], 16)
-    } else if (num_s.starts_with("0b") || num_s.starts_with("0B")) && num_s.len() > 2 {
-        i64::from_str_radix(&num_s[2..], 2)
-    } else {
-        i64::from_str_radix(&num_s, 10)
-    };
-
-    if let Ok(num) = num {
-        Ok(num)
-    } else {
-        Err(anyhow::anyhow!("Invalid value: {}", num_s))
+fn parse_string(parser: &mut Parser) -> Result<String, Error> {
+    match parser.peek() {
+        Some(Token {
+            kind: TokenKind::Str(s),
+            ..
+        }) => {
+            parser.bump();
+            Ok(s.clone())
+        }
+        _ => Err(parser.unexpected("string literal")),
     }
 }
 
-fn parse_char(ch: &str) -> anyhow::Result<char> {
-    let chars = ch.chars().collect::<Vec<_>>();
-    // シングルクォーテーションで囲まれていることを検査
-    if chars[0] != '\'' || chars[2] != '\'' {
-        return Err(anyhow::anyhow!(
-            "Unexpected identifier(expect: \"'\"): {}",
-            ch
-        ));
-    }
-    Ok(chars[1])
-}
+#[cfg(test)]
+mod tests {
+    use crate::assemble;
+    use crate::error::{ErrorKind, Errors};
 
-fn parse_string(string: &str) -> anyhow::Result<String> {
-    let chars = string.chars().collect::<Vec<_>>();
-    // ダブルクォーテーションで囲まれていることを検査
-    if chars[0] != '\"' || chars[chars.len() - 1] != '\"' {
-        return Err(anyhow::anyhow!(
-            "Unexpected identifier(expect: '\"'): {}",
-            string
-        ));
+    #[test]
+    fn char_out_of_byte() {
+        let (data, _) = assemble(".data\n    char 'A', '\u{FF}'\n", 6).unwrap();
+        assert_eq!(data.trim(), "00000000FF41");
+        let err = assemble(".data\n    char 'あ'\n", 6).unwrap_err();
+        let errors = err.downcast_ref::<Errors>().unwrap();
+        let kinds = errors.iter().map(|err| &err.kind).collect::<Vec<_>>();
+        assert!(
+            matches!(kinds[..], [ErrorKind::InvalidChar(_)]),
+            "{:?}",
+            kinds
+        );
     }
-    Ok(chars[1..chars.len() - 1].iter().collect())
 }
@@ -0,0 +1,421 @@
+use thiserror::Error;
+
+use crate::source::SourceMap;
+
+// ソース上の位置 (file は SourceMap の番号, line, col は 1 始まり, len は byte 数)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub file: usize,
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, col: usize, len: usize) -> Span {
+        Span {
+            file: 0,
+            line,
+            col,
+            len,
+        }
+    }
+
+    pub fn with_file(self, file: usize) -> Span {
+        Span { file, ..self }
+    }
+
+    // self から end までを覆う Span (同じ行の場合のみ)
+    pub fn to(self, end: Span) -> Span {
+        if self.file == end.file && self.line == end.line && self.col <= end.col {
+            Span {
+                len: end.col + end.len - self.col,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ErrorKind {
+    // 入力全体
+    #[error("Section \".text\" (or separator \"===\") is not found")]
+    SeparatorNotFound,
+    #[error("Invalid chunk size: {0}")]
+    InvalidChunkSize(usize),
+    #[error("Cannot read {path}: {reason}")]
+    CannotRead { path: String, reason: String },
+    #[error("Include cycle detected: {0}")]
+    IncludeCycle(String),
+
+    // 構文解析
+    #[error("Invalid command: {0}")]
+    InvalidCommand(String),
+    #[error("Invalid instruction: {0}")]
+    InvalidInstruction(String),
+    #[error("Invalid value: {0}")]
+    InvalidValue(String),
+    #[error("Invalid label: {0}")]
+    InvalidLabel(String),
+    #[error("Invalid character literal: {0}")]
+    InvalidChar(String),
+    #[error("Invalid escape sequence: \\{0}")]
+    InvalidEscape(char),
+    #[error("Unterminated literal (missing {0})")]
+    UnterminatedQuote(char),
+    #[error("Unexpected character: {0:?}")]
+    UnexpectedChar(char),
+    #[error("Unexpected token(expect: {expect}): {found}")]
+    UnexpectedToken { expect: String, found: String },
+
+    // 定数
+    #[error("Constant {0} is already defined")]
+    ConstRedefined(String),
+    #[error("Constant {0} is not defined")]
+    ConstNotFound(String),
+
+    // マクロ
+    #[error("Unterminated macro definition: {0} (missing .endm)")]
+    UnterminatedMacro(String),
+    #[error("Macro definitions cannot be nested")]
+    NestedMacro,
+    #[error(".endm without .macro")]
+    UnexpectedEndm,
+    #[error("Macro {0} is already defined")]
+    MacroRedefined(String),
+    #[error("Duplicate macro parameter: {0}")]
+    DuplicateMacroParam(String),
+    #[error("Macro {name} takes {expect} argument(s) but {found} were given")]
+    MacroArgCount {
+        name: String,
+        expect: usize,
+        found: usize,
+    },
+    #[error("Recursive macro invocation: {0}")]
+    RecursiveMacro(String),
+    #[error("Macro expansion produces more than {0} lines")]
+    MacroExpansionTooLarge(usize),
+
+    // 意味解析
+    #[error("label {0} is not found")]
+    LabelNotFound(String),
+    #[error("global label {0} is not defined")]
+    GlobalNotDefined(String),
+    #[error("Datalabel is not permitted in branch instruction: ${0}")]
+    DataLabelInBranch(String),
+    #[error("Invalid register usage: {field} = r{reg} (expect: r0-r{max})")]
+    InvalidRegister {
+        field: &'static str,
+        reg: u8,
+        max: u8,
+    },
+    #[error("Imm is overflow: {imm} (expect: {min}..={max})")]
+    ImmOverflow { imm: i64, min: i64, max: i64 },
+    #[error("Arithmetic overflow in expression: {0}")]
+    ExprOverflow(String),
+    #[error("Division by zero in expression: {0}")]
+    DivisionByZero(String),
+    #[error("Expression is nested too deeply (limit: {0})")]
+    ExprTooDeep(usize),
+
+    // リンク
+    #[error("global label {0} is defined multiple times")]
+    GlobalRedefined(String),
+    #[error(
+        "{memory} memory overflows: {used} bytes are used, but the capacity is {capacity} bytes"
+    )]
+    MemoryOverflow {
+        memory: String,
+        used: usize,
+        capacity: usize,
+    },
+
+    // オブジェクトファイル
+    #[error("Invalid object file: {0}")]
+    InvalidObject(String),
+
+    // 出力
+    #[error("Image has {words} words, but the memory depth is {depth}")]
+    ImageTooLarge { words: usize, depth: usize },
+    #[error("Address {address:#X} is not aligned to the word size ({chunk_size} bytes)")]
+    UnalignedAddress { address: usize, chunk_size: usize },
+    #[error("Address {0:#X} does not fit in the 32-bit addresses of the records")]
+    AddressTooLarge(usize),
+    #[error("Label {0} is defined more than once and cannot be told apart by its file")]
+    DuplicateConstant(String),
+
+    // メモリマップ
+    #[error("Invalid memory map: {0}")]
+    InvalidMemoryMap(String),
+    #[error("memory regions {0} and {1} overlap")]
+    RegionsOverlap(String, String),
+
+    // Intel HEX / S-record
+    #[error("Invalid record: {0}")]
+    InvalidRecord(String),
+
+    // 逆アセンブル
+    #[error("Invalid hex(expect: {digits} digits): {line}")]
+    InvalidHex { line: String, digits: usize },
+    #[error("Incomplete instruction at the end of input")]
+    IncompleteInst,
+    #[error("Unknown instruction: 0x{0:012X}")]
+    UnknownInst(u64),
+}
+
+#[derive(Debug, Error)]
+#[error("{kind}")]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: Span,
+    // 補足 (擬似命令の展開結果など)
+    pub note: Option<String>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, span: Span) -> Error {
+        Error {
+            kind,
+            span,
+            note: None,
+        }
+    }
+
+    pub fn with_note(self, note: Option<String>) -> Error {
+        Error { note, ..self }
+    }
+
+    // ソース上の位置を持たないエラー
+    pub fn global(kind: ErrorKind) -> Error {
+        Error::new(kind, Span::default())
+    }
+
+    // rustc 風のエラーメッセージを生成する
+    //
+    // error: label foo is not found
+    //  --> main.asm:9:20
+    //   |
+    // 9 |     addi r10 = r0, $foo
+    //   |                    ^^^^
+    //   = note: ...
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let Span {
+            file,
+            line,
+            col,
+            len,
+        } = self.span;
+        let Some(source_file) = sources.get(file).filter(|_| line != 0) else {
+            let mut result = format!("error: {}\n", self.kind);
+            if let Some(note) = &self.note {
+                result += &format!("note: {}\n", note);
+            }
+            return result;
+        };
+        let path = source_file.path.display();
+        let source = &source_file.source;
+
+        let line_s = line.to_string();
+        let pad = " ".repeat(line_s.len());
+
+        let mut result = format!(
+            "error: {}\n{}--> {}:{}:{}\n",
+            self.kind, pad, path, line, col
+        );
+        if let Some(text) = source.lines().nth(line.wrapping_sub(1)) {
+            // タブ等の幅を合わせるため, 空白文字はそのまま残す
+            let indent = text
+                .get(..col.saturating_sub(1))
+                .unwrap_or("")
+                .chars()
+                .map(|c| if c.is_whitespace() { c } else { ' ' })
+                .collect::<String>();
+            let carets = text
+                .get(col.saturating_sub(1)..(col.saturating_sub(1) + len))
+                .map(|s| s.chars().count())
+                .unwrap_or(1)
+                .max(1);
+            result += &format!("{} |\n", pad);
+            result += &format!("{} | {}\n", line_s, text);
+            result += &format!("{} | {}{}\n", pad, indent, "^".repeat(carets));
+        }
+        if let Some(note) = &self.note {
+            result += &format!("{} = note: {}\n", pad, note);
+        }
+        result
+    }
+}
+
+// 複数のエラーを蓄積する (limit を超えた分は件数のみ数える)
+#[derive(Debug, Default, Error)]
+#[error("{}", self.summary())]
+pub struct Errors {
+    errors: Vec<Error>,
+    limit: Option<usize>,
+    suppressed: usize,
+}
+
+impl Errors {
+    pub fn new(limit: Option<usize>) -> Errors {
+        Errors {
+            errors: Vec::new(),
+            limit,
+            suppressed: 0,
+        }
+    }
+
+    pub fn push(&mut self, err: Error) {
+        if self.is_full() {
+            self.suppressed += 1;
+        } else {
+            self.errors.push(err);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.limit.is_some_and(|limit| self.errors.len() >= limit)
+    }
+
+    // 上限を超えて捨てた分も含めた件数
+    pub fn count(&self) -> usize {
+        self.errors.len() + self.suppressed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Error> {
+        self.errors.iter()
+    }
+
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut result = String::new();
+        for err in &self.errors {
+            result += &err.render(sources);
+            result += "\n";
+        }
+        result += &format!("error: {}\n", aborting(self.count()));
+        if self.suppressed > 0 {
+            result += &format!(
+                "note: {} errors not shown (error limit: {})\n",
+                self.suppressed,
+                self.errors.len()
+            );
+        }
+        result
+    }
+
+    // 最初のエラーのメッセージと件数 (Display 用)
+    fn summary(&self) -> String {
+        match self.errors.first() {
+            Some(first) => format!("{} ({})", first, aborting(self.count())),
+            None => aborting(self.count()),
+        }
+    }
+
+    // エラーが 1 つもなければ Ok
+    pub fn into_result(self) -> Result<(), Errors> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+fn aborting(count: usize) -> String {
+    match count {
+        1 => "aborting due to 1 previous error".to_string(),
+        count => format!("aborting due to {} previous errors", count),
+    }
+}
+
+impl From<Error> for Errors {
+    fn from(err: Error) -> Errors {
+        let mut errors = Errors::new(None);
+        errors.push(err);
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_display() {
+        let mut errors = Errors::new(Some(1));
+        assert_eq!(errors.to_string(), "aborting due to 0 previous errors");
+        errors.push(Error::global(ErrorKind::InvalidLabel("foo".to_string())));
+        assert_eq!(
+            errors.to_string(),
+            "Invalid label: foo (aborting due to 1 previous error)"
+        );
+        errors.push(Error::global(ErrorKind::InvalidLabel("bar".to_string())));
+        assert_eq!(
+            errors.to_string(),
+            "Invalid label: foo (aborting due to 2 previous errors)"
+        );
+    }
+
+    #[test]
+    fn render() {
+        let sources =
+            SourceMap::single("main.asm", "$a\n    byte1 1\n===\n    addi r1 = r0, $foo\n");
+        let err = Error::new(
+            ErrorKind::LabelNotFound("$foo".to_string()),
+            Span::new(4, 19, 4),
+        )
+        .with_note(Some("labels are case sensitive".to_string()));
+        assert_eq!(
+            err.render(&sources),
+            format!(
+                "error: {}\n --> main.asm:4:19\n  |\n4 |     addi r1 = r0, $foo\n  |                   ^^^^\n  = note: labels are case sensitive\n",
+                err.kind
+            )
+        );
+    }
+
+    #[test]
+    fn error_limit() {
+        // 上限を超えた分は件数だけ数える
+        let mut errors = Errors::new(Some(2));
+        for name in ["a", "b", "c", "d", "e"] {
+            errors.push(Error::global(ErrorKind::InvalidLabel(name.to_string())));
+        }
+        assert!(errors.is_full());
+        assert_eq!(errors.iter().count(), 2);
+        assert_eq!(errors.count(), 5);
+        assert_eq!(
+            errors.render(&SourceMap::new()),
+            "error: Invalid label: a\n\nerror: Invalid label: b\n\n\
+             error: aborting due to 5 previous errors\n\
+             note: 3 errors not shown (error limit: 2)\n"
+        );
+
+        // 上限がなければ注記も出さない
+        let mut errors = Errors::new(None);
+        errors.push(Error::global(ErrorKind::InvalidLabel("a".to_string())));
+        assert!(!errors.is_full());
+        assert!(!errors.render(&SourceMap::new()).contains("not shown"));
+    }
+
+    #[test]
+    fn error_limit_option() {
+        let program = "===\n    foo\n    bar\n    baz\n";
+        let options = crate::Options {
+            error_limit: Some(1),
+            ..crate::Options::default()
+        };
+        let err = crate::assemble_with(program, &options).unwrap_err();
+        let errors = err.downcast::<Errors>().unwrap();
+        assert_eq!(errors.iter().count(), 1);
+        assert_eq!(errors.count(), 3);
+        assert!(errors
+            .render(&SourceMap::new())
+            .ends_with("note: 2 errors not shown (error limit: 1)\n"));
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::ErrorKind;
+
+// 式の入れ子の深さの上限
+// (式は再帰で読み書き・計算するので, 深すぎる式でスタックを使い切らないようにする)
+pub const MAX_DEPTH: usize = 256;
+
+// オペランドの式
+// 1 + 2, $table + 4, @end - @start, (1 << 12) | 3, -(SIZE)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(i64),
+    DataLabel(String),
+    InstLabel(String),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg, // -
+    Not, // ~
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add, // +
+    Sub, // -
+    Mul, // *
+    Div, // /
+    Rem, // %
+    Shl, // <<
+    Shr, // >> (算術シフト)
+    And, // &
+    Or,  // |
+    Xor, // ^
+}
+
+impl BinOp {
+    #[rustfmt::skip]
+    pub fn from_punct(punct: &str) -> Option<BinOp> {
+        match punct {
+            "+" => Some(BinOp::Add),
+            "-" => Some(BinOp::Sub),
+            "*" => Some(BinOp::Mul),
+            "/" => Some(BinOp::Div),
+            "%" => Some(BinOp::Rem),
+            "<<" => Some(BinOp::Shl),
+            ">>" => Some(BinOp::Shr),
+            "&" => Some(BinOp::And),
+            "|" => Some(BinOp::Or),
+            "^" => Some(BinOp::Xor),
+            _ => None,
+        }
+    }
+
+    // 結合の強さ (C と同じ順)
+    pub fn prec(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::Xor => 2,
+            BinOp::And => 3,
+            BinOp::Shl | BinOp::Shr => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 6,
+        }
+    }
+
+    pub fn punct(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::And => "&",
+            BinOp::Or => "|",
+            BinOp::Xor => "^",
+        }
+    }
+}
+
+impl Expr {
+    // 式に含まれるラベルを (接頭辞, 名前) で列挙する
+    pub fn labels(&self) -> Vec<(char, &str)> {
+        match self {
+            Expr::Num(_) => Vec::new(),
+            Expr::DataLabel(label) => vec![('$', label)],
+            Expr::InstLabel(label) => vec![('@', label)],
+            Expr::Unary(_, expr) => expr.labels(),
+            Expr::Binary(_, lhs, rhs) => {
+                let mut labels = lhs.labels();
+                labels.extend(rhs.labels());
+                labels
+            }
+        }
+    }
+
+    // 命令のラベルを f が返す名前に置き換える (None ならそのまま)
+    pub fn rename_inst_labels(&mut self, f: &mut impl FnMut(&str) -> Option<String>) {
+        match self {
+            Expr::InstLabel(label) => {
+                if let Some(renamed) = f(label) {
+                    *label = renamed;
+                }
+            }
+            Expr::Unary(_, expr) => expr.rename_inst_labels(f),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.rename_inst_labels(f);
+                rhs.rename_inst_labels(f);
+            }
+            Expr::Num(_) | Expr::DataLabel(_) => {}
+        }
+    }
+
+    // 命令のアドレスを表す式か (@label, @label + 6 など)
+    // 分岐命令では, アドレスなら pc からの相対値に直し, そうでなければそのまま相対値として扱う
+    pub fn is_inst_addr(&self) -> bool {
+        match self {
+            Expr::InstLabel(_) => true,
+            Expr::Binary(BinOp::Add, lhs, rhs) => lhs.is_inst_addr() != rhs.is_inst_addr(),
+            Expr::Binary(BinOp::Sub, lhs, rhs) => lhs.is_inst_addr() && !rhs.is_inst_addr(),
+            _ => false,
+        }
+    }
+
+    // ラベルはそれぞれのアドレス (byte) として計算する
+    pub fn eval(
+        &self,
+        data_labels: &HashMap<String, usize>,
+        inst_labels: &HashMap<String, usize>,
+    ) -> Result<i64, ErrorKind> {
+        let overflow = || ErrorKind::ExprOverflow(self.to_string());
+        match self {
+            Expr::Num(num) => Ok(*num),
+            Expr::DataLabel(label) => data_labels
+                .get(label)
+                .map(|&addr| addr as i64)
+                .ok_or_else(|| ErrorKind::LabelNotFound(format!("${}", label))),
+            Expr::InstLabel(label) => inst_labels
+                .get(label)
+                .map(|&addr| addr as i64)
+                .ok_or_else(|| ErrorKind::LabelNotFound(format!("@{}", label))),
+            Expr::Unary(op, expr) => {
+                let value = expr.eval(data_labels, inst_labels)?;
+                match op {
+                    UnOp::Neg => value.checked_neg().ok_or_else(overflow),
+                    UnOp::Not => Ok(!value),
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(data_labels, inst_labels)?;
+                let rhs = rhs.eval(data_labels, inst_labels)?;
+                let shift = || u32::try_from(rhs).ok().filter(|&rhs| rhs < 64);
+                #[rustfmt::skip]
+                let value = match op {
+                    BinOp::Add => lhs.checked_add(rhs),
+                    BinOp::Sub => lhs.checked_sub(rhs),
+                    BinOp::Mul => lhs.checked_mul(rhs),
+                    BinOp::Div if rhs == 0 => return Err(ErrorKind::DivisionByZero(self.to_string())),
+                    BinOp::Div => lhs.checked_div(rhs),
+                    BinOp::Rem if rhs == 0 => return Err(ErrorKind::DivisionByZero(self.to_string())),
+                    BinOp::Rem => lhs.checked_rem(rhs),
+                    // はみ出した bit があればオーバーフロー
+                    BinOp::Shl => shift().and_then(|rhs| Some(lhs << rhs).filter(|value| value >> rhs == lhs)),
+                    BinOp::Shr => shift().and_then(|rhs| lhs.checked_shr(rhs)),
+                    BinOp::And => Some(lhs & rhs),
+                    BinOp::Or => Some(lhs | rhs),
+                    BinOp::Xor => Some(lhs ^ rhs),
+                };
+                value.ok_or_else(overflow)
+            }
+        }
+    }
+}
+
+// ソースに書ける形で出力する (優先順位に関わらず二項演算は括弧で囲む)
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Num(num) => write!(f, "{}", num),
+            Expr::DataLabel(label) => write!(f, "${}", label),
+            Expr::InstLabel(label) => write!(f, "@{}", label),
+            Expr::Unary(UnOp::Neg, expr) => write!(f, "-{}", expr),
+            Expr::Unary(UnOp::Not, expr) => write!(f, "~{}", expr),
+            Expr::Binary(op, lhs, rhs) => write!(f, "({} {} {})", lhs, op.punct(), rhs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shl(lhs: i64, rhs: i64) -> Result<i64, ErrorKind> {
+        let expr = Expr::Binary(BinOp::Shl, Box::new(Expr::Num(lhs)), Box::new(Expr::Num(rhs)));
+        expr.eval(&HashMap::new(), &HashMap::new())
+    }
+
+    #[test]
+    fn shift_overflow() {
+        assert_eq!(shl(1, 62).unwrap(), 1 << 62);
+        assert_eq!(shl(-1, 63).unwrap(), i64::MIN);
+        assert!(matches!(shl(1, 63), Err(ErrorKind::ExprOverflow(_))));
+        assert!(matches!(shl(3, 62), Err(ErrorKind::ExprOverflow(_))));
+        assert!(matches!(shl(1, 64), Err(ErrorKind::ExprOverflow(_))));
+    }
+}
@@ -0,0 +1,158 @@
+// data.hex, inst.hex 以外の出力形式
+pub mod coe;
+pub mod ihex;
+pub mod mif;
+pub mod chisel;
+pub mod srec;
+pub mod verilog;
+
+use std::collections::BTreeMap;
+
+use crate::error::{Error, ErrorKind, Errors, Span};
+
+// 読み込むレコードの, 最も小さいアドレスから最後の byte までの byte 数の上限
+pub const MAX_IMAGE_LEN: usize = 1 << 24;
+
+// 1 行のレコードから読んだ byte を, アドレスごとに集める
+struct Records {
+    bytes: BTreeMap<usize, (u8, Span)>,
+    errors: Errors,
+}
+
+impl Records {
+    fn new() -> Records {
+        Records {
+            bytes: BTreeMap::new(),
+            errors: Errors::new(None),
+        }
+    }
+
+    fn insert(&mut self, address: usize, data: &[u8], span: Span) {
+        for (idx, &byte) in data.iter().enumerate() {
+            self.bytes.insert(address + idx, (byte, span));
+        }
+    }
+
+    fn error(&mut self, reason: impl Into<String>, span: Span) {
+        self.errors
+            .push(Error::new(ErrorKind::InvalidRecord(reason.into()), span));
+    }
+
+    // 最も小さいアドレスと, そこから最後の byte までの列 (間は 0 で埋める)
+    fn finish(self) -> Result<(usize, Vec<(u8, Span)>), Errors> {
+        self.errors.into_result()?;
+        let (Some((&start, _)), Some((&end, &(_, span)))) =
+            (self.bytes.first_key_value(), self.bytes.last_key_value())
+        else {
+            return Ok((0, Vec::new()));
+        };
+        if end - start >= MAX_IMAGE_LEN {
+            let reason = format!(
+                "records span from {:#X} to {:#X}, more than {} bytes",
+                start, end, MAX_IMAGE_LEN
+            );
+            return Err(Error::new(ErrorKind::InvalidRecord(reason), span).into());
+        }
+        let mut result = Vec::new();
+        for (address, byte) in self.bytes {
+            result.resize(address - start, (0, Span::default()));
+            result.push(byte);
+        }
+        Ok((start, result))
+    }
+}
+
+// 16 進数 2 桁ずつの列を byte 列にする
+fn parse_bytes(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&digits[idx..(idx + 2)], 16).ok())
+        .collect()
+}
+
+fn to_digits(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+// メモリ初期化ファイルの値の基数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Bin,
+    Dec,
+    Hex,
+}
+
+impl Radix {
+    // 上位 byte から並べた語を, 語の幅に合わせて 0 埋めした数字列にする (10 進数は 0 埋めしない)
+    fn digits(self, word: &[u8]) -> String {
+        match self {
+            Radix::Bin => word.iter().map(|byte| format!("{:08b}", byte)).collect(),
+            Radix::Hex => to_digits(word),
+            Radix::Dec => {
+                // 10 で割った余りを下の桁から集める
+                let mut word = word.to_vec();
+                let mut digits = Vec::new();
+                loop {
+                    let mut rem = 0;
+                    for byte in &mut word {
+                        let value = (rem << 8) | *byte as u32;
+                        *byte = (value / 10) as u8;
+                        rem = value % 10;
+                    }
+                    digits.push(char::from(b'0' + rem as u8));
+                    if word.iter().all(|&byte| byte == 0) {
+                        break;
+                    }
+                }
+                digits.into_iter().rev().collect()
+            }
+        }
+    }
+}
+
+// chunk_size byte ごとの語 (上位 byte から)
+// 最後の語の残りと, depth を指定したときはそこまでの語を fill で埋める
+fn words(
+    mut bytes: Vec<u8>,
+    chunk_size: usize,
+    depth: Option<usize>,
+    fill: u8,
+) -> Result<Vec<Vec<u8>>, Error> {
+    if chunk_size == 0 {
+        return Err(Error::global(ErrorKind::InvalidChunkSize(0)));
+    }
+    bytes.resize(bytes.len().next_multiple_of(chunk_size), fill);
+    let mut words = bytes
+        .chunks(chunk_size)
+        .map(|chunk| chunk.iter().rev().copied().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    if let Some(depth) = depth {
+        if words.len() > depth {
+            return Err(image_too_large(words.len(), depth));
+        }
+        words.resize(depth, vec![fill; chunk_size]);
+    }
+    Ok(words)
+}
+
+// depth 語を指すのに必要なアドレスの bit 数
+fn addr_width(depth: usize) -> usize {
+    let width = usize::BITS - depth.saturating_sub(1).leading_zeros();
+    (width as usize).max(1)
+}
+
+// address から len byte が 32bit のアドレスに収まるか
+fn check_address(address: usize, len: usize) -> Result<(), Error> {
+    let last = address.saturating_add(len.saturating_sub(1));
+    if last > u32::MAX as usize {
+        return Err(Error::global(ErrorKind::AddressTooLarge(last)));
+    }
+    Ok(())
+}
+
+fn image_too_large(words: usize, depth: usize) -> Error {
+    Error::global(ErrorKind::ImageTooLarge { words, depth })
+}
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+
+use super::{to_digits, words};
+use crate::error::{Error, ErrorKind};
+use crate::object::Section;
+use crate::symbol_map::SymbolMap;
+
+#[rustfmt::skip]
+const KEYWORDS: &[&str] = &[
+    "abstract", "case", "catch", "class", "def", "do", "else", "extends", "false", "final",
+    "finally", "for", "forSome", "if", "implicit", "import", "lazy", "match", "new", "null",
+    "object", "override", "package", "private", "protected", "return", "sealed", "super", "this",
+    "throw", "trait", "true", "try", "type", "val", "var", "while", "with", "yield",
+];
+
+// Chisel の ROM とテストベンチで共有する Scala の object にする
+// 命令は chunk_size byte の語, データは byte ごとの VecInit で, ラベルのアドレス (byte) を定数にする
+// (VecInit はモジュールの中で呼ぶので def にする)
+//
+// object helloworld {
+//   val instWidth = 48
+//   def inst: Vec[UInt] = VecInit(Seq(
+//     "h000001000222",
+//     ...
+//   ).map(_.U(instWidth.W)))
+//   ...
+//   object instLabels {
+//     val func_main = 0x0
+//     val `func_main.loop` = 0x12
+pub fn write(
+    data: &[u8],
+    text: &[u8],
+    chunk_size: usize,
+    symbols: &SymbolMap,
+    name: &str,
+    package: Option<&str>,
+) -> Result<String, Error> {
+    let inst_words = words(text.to_vec(), chunk_size, None, 0)?;
+    let data_words = words(data.to_vec(), 1, None, 0)?;
+
+    let mut result = String::from("// Generated by sb_assembler. Do not edit.\n");
+    if let Some(package) = package {
+        result += &format!("package {}\n", package);
+    }
+    result += "\nimport chisel3._\n\n";
+    result += &format!("object {} {{\n", identifier(name));
+    result += &format!("  val instWidth = {}\n", chunk_size * 8);
+    result += "  val dataWidth = 8\n\n";
+    result += &vec_init("inst", "instWidth", &inst_words, chunk_size);
+    result += "\n";
+    result += &vec_init("data", "dataWidth", &data_words, 1);
+
+    for (section, object) in [(Section::Data, "dataLabels"), (Section::Text, "instLabels")] {
+        result += &format!("\n  object {} {{\n", object);
+        let entries = symbols
+            .entries
+            .iter()
+            .filter(|entry| entry.section == section)
+            .collect::<Vec<_>>();
+        // 別のオブジェクトにある同じ名前のラベルは, ソースファイルの名前を付けて区別する (main:loop)
+        let mut defined = HashSet::new();
+        for entry in &entries {
+            let duplicated = entries
+                .iter()
+                .filter(|other| other.name == entry.name)
+                .count()
+                > 1;
+            let stem = entry.file.as_ref().and_then(|file| file.file_stem());
+            let name = match stem {
+                Some(stem) if duplicated => format!("{}:{}", stem.to_string_lossy(), entry.name),
+                _ => entry.name.clone(),
+            };
+            if !defined.insert(name.clone()) {
+                return Err(Error::global(ErrorKind::DuplicateConstant(name)));
+            }
+            // Int に収まらないアドレスは Long にする (0x80000000 は負の Int になる)
+            let suffix = if entry.address > i32::MAX as usize {
+                "L"
+            } else {
+                ""
+            };
+            result += &format!(
+                "    val {} = 0x{:X}{}\n",
+                identifier(&name),
+                entry.address,
+                suffix
+            );
+        }
+        result += "  }\n";
+    }
+    result += "}\n";
+    Ok(result)
+}
+
+fn vec_init(name: &str, width: &str, words: &[Vec<u8>], chunk_size: usize) -> String {
+    // 空の VecInit は作れないので, 空のイメージは 0 の 1 語にする
+    let zero = [vec![0; chunk_size]];
+    let words = if words.is_empty() { &zero[..] } else { words };
+    let mut result = format!("  def {}: Vec[UInt] = VecInit(Seq(\n", name);
+    for (idx, word) in words.iter().enumerate() {
+        let comma = if idx + 1 < words.len() { "," } else { "" };
+        result += &format!("    \"h{}\"{}\n", to_digits(word), comma);
+    }
+    result + &format!("  ).map(_.U({}.W)))\n", width)
+}
+
+// Scala の識別子にできない名前 (func_main.loop, main:loop, 予約語) は `` で囲む
+fn identifier(name: &str) -> String {
+    let plain = name
+        .chars()
+        .enumerate()
+        .all(|(idx, c)| c == '_' || c.is_ascii_alphabetic() || (idx > 0 && c.is_ascii_digit()));
+    if plain && !name.is_empty() && !KEYWORDS.contains(&name) {
+        name.to_string()
+    } else {
+        format!("`{}`", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol_map::Entry;
+
+    fn entry(name: &str, section: Section, address: usize, file: &str) -> Entry {
+        Entry {
+            name: name.to_string(),
+            section,
+            address,
+            size: 0,
+            global: false,
+            file: Some(file.into()),
+        }
+    }
+
+    #[test]
+    fn object() {
+        let symbols = SymbolMap {
+            entries: vec![
+                entry("buf", Section::Data, 0x0, "main.asm"),
+                entry("func_main", Section::Text, 0x0, "main.asm"),
+                entry("func_main.loop", Section::Text, 0x6, "main.asm"),
+                entry("type", Section::Text, 0x80000000, "main.asm"),
+            ],
+        };
+        let text = [
+            0x22, 0x02, 0x00, 0x01, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        let scala = write(&[0x12], &text, 6, &symbols, "hello-world", Some("rom")).unwrap();
+        assert_eq!(
+            scala,
+            r#"// Generated by sb_assembler. Do not edit.
+package rom
+
+import chisel3._
+
+object `hello-world` {
+  val instWidth = 48
+  val dataWidth = 8
+
+  def inst: Vec[UInt] = VecInit(Seq(
+    "h000001000222",
+    "hFFFFFFFFFFFF"
+  ).map(_.U(instWidth.W)))
+
+  def data: Vec[UInt] = VecInit(Seq(
+    "h12"
+  ).map(_.U(dataWidth.W)))
+
+  object dataLabels {
+    val buf = 0x0
+  }
+
+  object instLabels {
+    val func_main = 0x0
+    val `func_main.loop` = 0x6
+    val `type` = 0x80000000L
+  }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let scala = write(&[], &[], 6, &SymbolMap::default(), "empty", None).unwrap();
+        assert!(!scala.contains("Seq()"));
+        assert!(scala.contains("VecInit(Seq(\n    \"h000000000000\"\n  ).map(_.U(instWidth.W)))"));
+        assert!(scala.contains("VecInit(Seq(\n    \"h00\"\n  ).map(_.U(dataWidth.W)))"));
+        assert!(scala.contains("  object instLabels {\n  }\n"));
+    }
+
+    #[test]
+    fn duplicate_labels() {
+        // 別のファイルの同じ名前はファイル名で区別する
+        let symbols = SymbolMap {
+            entries: vec![
+                entry("loop", Section::Text, 0x0, "main.asm"),
+                entry("loop", Section::Text, 0x6, "lib.asm"),
+            ],
+        };
+        let scala = write(&[], &[0; 12], 6, &symbols, "rom", None).unwrap();
+        assert!(scala.contains("    val `main:loop` = 0x0\n    val `lib:loop` = 0x6\n"));
+
+        // ファイル名を付けても区別できないとき
+        let symbols = SymbolMap {
+            entries: vec![
+                entry("loop", Section::Text, 0x0, "a/main.asm"),
+                entry("loop", Section::Text, 0x6, "b/main.asm"),
+            ],
+        };
+        let err = write(&[], &[0; 12], 6, &symbols, "rom", None).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::DuplicateConstant(name) if name == "main:loop"));
+    }
+}
@@ -0,0 +1,76 @@
+use super::{words, Radix};
+use crate::error::Error;
+
+// Xilinx (Vivado の Block Memory Generator) の .coe にする
+// 1 語は chunk_size byte で, depth を指定したときはそこまで fill の byte で埋める
+//
+// memory_initialization_radix=16;
+// memory_initialization_vector=
+// 000001000222,
+// 000000000A22,
+// ...
+// 000000000000;
+pub fn write(
+    bytes: Vec<u8>,
+    chunk_size: usize,
+    radix: Radix,
+    depth: Option<usize>,
+    fill: u8,
+) -> Result<String, Error> {
+    let words = words(bytes, chunk_size, depth, fill)?;
+    let radix_number = match radix {
+        Radix::Bin => 2,
+        Radix::Dec => 10,
+        Radix::Hex => 16,
+    };
+    let vector = words
+        .iter()
+        .map(|word| radix.digits(word))
+        .collect::<Vec<_>>();
+    Ok(format!(
+        "memory_initialization_radix={};\nmemory_initialization_vector=\n{};\n",
+        radix_number,
+        vector.join(",\n")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radixes() {
+        let bytes = vec![0x22, 0x02, 0xFF, 0xFF];
+        let coe = write(bytes.clone(), 2, Radix::Hex, None, 0).unwrap();
+        assert_eq!(
+            coe,
+            "memory_initialization_radix=16;\nmemory_initialization_vector=\n0222,\nFFFF;\n"
+        );
+        let coe = write(bytes.clone(), 2, Radix::Bin, None, 0).unwrap();
+        assert_eq!(
+            coe,
+            "memory_initialization_radix=2;\nmemory_initialization_vector=\n\
+             0000001000100010,\n1111111111111111;\n"
+        );
+        let coe = write(bytes, 2, Radix::Dec, None, 0).unwrap();
+        assert_eq!(
+            coe,
+            "memory_initialization_radix=10;\nmemory_initialization_vector=\n546,\n65535;\n"
+        );
+    }
+
+    #[test]
+    fn depth() {
+        // 最後の語の残りと depth までの語は fill で埋める
+        let coe = write(vec![0x22, 0x02, 0x0A], 2, Radix::Hex, Some(3), 0xFF).unwrap();
+        assert_eq!(
+            coe,
+            "memory_initialization_radix=16;\nmemory_initialization_vector=\n0222,\nFF0A,\nFFFF;\n"
+        );
+        let err = write(vec![0; 4], 1, Radix::Hex, Some(3), 0).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            crate::error::ErrorKind::ImageTooLarge { words: 4, depth: 3 }
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use super::{check_address, parse_bytes, to_digits, Records};
+use crate::error::{Error, Errors, Span};
+
+// 1 レコードのデータの最大 byte 数
+pub const MAX_RECORD_LEN: usize = 255;
+
+const DATA: u8 = 0x00;
+const END: u8 = 0x01;
+const SEGMENT: u8 = 0x02;
+const LINEAR: u8 = 0x04;
+
+// Intel HEX にする (address: 先頭の byte を置くアドレス, record_len: 1 レコードのデータの byte 数)
+// 64KiB を超えるアドレスには拡張リニアアドレスレコードを使う (32bit を超えるアドレスはエラー)
+//
+// :10000000220200010000220A000000000301000695
+// :00000001FF
+pub fn write(bytes: &[u8], address: usize, record_len: usize) -> Result<String, Error> {
+    check_address(address, bytes.len())?;
+    let record_len = record_len.clamp(1, MAX_RECORD_LEN);
+    let mut result = String::new();
+    let mut upper = 0;
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let addr = address + offset;
+        if addr >> 16 != upper {
+            upper = addr >> 16;
+            result += &record(LINEAR, 0, &[(upper >> 8) as u8, upper as u8]);
+        }
+        // レコードは 64KiB の境界をまたがない
+        let len = record_len
+            .min(bytes.len() - offset)
+            .min(0x10000 - (addr & 0xFFFF));
+        result += &record(DATA, addr as u16, &bytes[offset..(offset + len)]);
+        offset += len;
+    }
+    Ok(result + &record(END, 0, &[]))
+}
+
+fn record(kind: u8, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+    bytes.extend(data);
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(sum.wrapping_neg());
+    format!(":{}\n", to_digits(&bytes))
+}
+
+// Intel HEX を (最も小さいアドレス, そこからの byte 列) に戻す
+// (各 byte にはそれが書かれている行を添える. レコードの間は 0 で埋める)
+pub fn parse(text: &str) -> Result<(usize, Vec<(u8, Span)>), Errors> {
+    let mut records = Records::new();
+    let mut base = 0;
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let span = Span::new(idx + 1, 1, line.len());
+        let bytes = match line.strip_prefix(':').and_then(parse_bytes) {
+            Some(bytes) if bytes.len() >= 5 && bytes.len() == bytes[0] as usize + 5 => bytes,
+            _ => {
+                records.error(format!("`{}` is not an Intel HEX record", line), span);
+                continue;
+            }
+        };
+        let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if sum != 0 {
+            records.error("checksum mismatch", span);
+            continue;
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        let data = &bytes[4..(bytes.len() - 1)];
+        match bytes[3] {
+            DATA => records.insert(base + address, data, span),
+            END => break,
+            SEGMENT if data.len() == 2 => {
+                base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 4;
+            }
+            LINEAR if data.len() == 2 => {
+                base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 16;
+            }
+            // 開始アドレスのレコードは読み飛ばす
+            0x03 | 0x05 => {}
+            kind => records.error(format!("unsupported record type {:02X}", kind), span),
+        }
+    }
+    records.finish()
+}
+
+// parse から byte 列だけを取り出す
+pub fn read(text: &str) -> Result<(usize, Vec<u8>), Errors> {
+    let (address, bytes) = parse(text)?;
+    Ok((address, bytes.into_iter().map(|(byte, _)| byte).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn round_trip() {
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        let text = write(&bytes, 0, 16).unwrap();
+        assert!(text.starts_with(":10000000000102030405060708090A0B0C0D0E0F78\n"));
+        assert!(text.ends_with(":00000001FF\n"));
+        assert_eq!(read(&text).unwrap(), (0, bytes.clone()));
+
+        // 64KiB の境界をまたぐもの
+        let text = write(&bytes, 0x1FFF8, 16).unwrap();
+        assert!(text.starts_with(":020000040001F9\n:08FFF800"));
+        assert!(text.contains(":020000040002F8\n:10000000"));
+        assert_eq!(read(&text).unwrap(), (0x1FFF8, bytes));
+    }
+
+    #[test]
+    fn gap() {
+        let text = [
+            record(DATA, 0x10, &[1, 2]),
+            record(DATA, 0x14, &[3]),
+            record(END, 0, &[]),
+        ]
+        .concat();
+        assert_eq!(read(&text).unwrap(), (0x10, vec![1, 2, 0, 0, 3]));
+
+        // 離れたアドレスのレコードはイメージを作らずにエラーにする
+        let text = [
+            record(DATA, 0, &[1]),
+            record(LINEAR, 0, &[0xFF, 0xF0]),
+            record(DATA, 0, &[2]),
+            record(END, 0, &[]),
+        ]
+        .concat();
+        let errors = parse(&text).unwrap_err();
+        let errors = errors.iter().collect::<Vec<_>>();
+        assert!(
+            matches!(errors[0].kind, ErrorKind::InvalidRecord(_)),
+            "{:?}",
+            errors
+        );
+        assert_eq!(errors[0].span.line, 3);
+    }
+
+    #[test]
+    fn address_too_large() {
+        assert!(write(&[1, 2], 0xFFFF_FFFE, 16).is_ok());
+        let err = write(&[1, 2], 0xFFFF_FFFF, 16).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::AddressTooLarge(0x1_0000_0000)),
+            "{:?}",
+            err
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use super::{addr_width, words, Radix};
+use crate::error::Error;
+
+// Intel (Altera) Quartus の .mif にする
+// 1 語は chunk_size byte で, depth (指定しないときはイメージの語数) までの残りは fill の byte で埋める
+//
+// WIDTH=48;
+// DEPTH=32;
+//
+// ADDRESS_RADIX=HEX;
+// DATA_RADIX=HEX;
+//
+// CONTENT BEGIN
+//     00 : 000001000222;
+//     ...
+//     [1A..1F] : 000000000000;
+// END;
+pub fn write(
+    bytes: Vec<u8>,
+    chunk_size: usize,
+    radix: Radix,
+    depth: Option<usize>,
+    fill: u8,
+) -> Result<String, Error> {
+    let words = words(bytes, chunk_size, depth, fill)?;
+    let depth = words.len();
+    // 末尾の fill だけの語は 1 行の範囲指定にまとめる
+    let filled = words
+        .iter()
+        .rev()
+        .take_while(|word| word.iter().all(|&byte| byte == fill))
+        .count();
+    let len = depth - filled;
+    let digits = addr_width(depth).div_ceil(4);
+    let radix_name = match radix {
+        Radix::Bin => "BIN",
+        Radix::Dec => "UNS",
+        Radix::Hex => "HEX",
+    };
+
+    let mut result = format!("WIDTH={};\nDEPTH={};\n\n", chunk_size * 8, depth);
+    result += &format!("ADDRESS_RADIX=HEX;\nDATA_RADIX={};\n\n", radix_name);
+    result += "CONTENT BEGIN\n";
+    for (idx, word) in words[..len].iter().enumerate() {
+        result += &format!("    {:0digits$X} : {};\n", idx, radix.digits(word));
+    }
+    match filled {
+        0 => {}
+        1 => result += &format!("    {:0digits$X} : {};\n", len, radix.digits(&words[len])),
+        _ => {
+            result += &format!(
+                "    [{:0digits$X}..{:0digits$X}] : {};\n",
+                len,
+                depth - 1,
+                radix.digits(&words[len])
+            )
+        }
+    }
+    result += "END;\n";
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth() {
+        // 末尾の fill だけの語は範囲指定の 1 行になる
+        let mif = write(vec![0x22, 0x02, 0x0A, 0x00], 2, Radix::Hex, Some(32), 0).unwrap();
+        assert_eq!(
+            mif,
+            "WIDTH=16;\nDEPTH=32;\n\nADDRESS_RADIX=HEX;\nDATA_RADIX=HEX;\n\nCONTENT BEGIN\n    \
+             00 : 0222;\n    01 : 000A;\n    [02..1F] : 0000;\nEND;\n"
+        );
+        // 残りが 1 語のときは範囲にしない
+        let mif = write(vec![0x22], 1, Radix::Hex, Some(2), 0xFF).unwrap();
+        assert!(mif.ends_with("CONTENT BEGIN\n    0 : 22;\n    1 : FF;\nEND;\n"));
+        // depth を指定しないときはイメージの語数
+        let mif = write(vec![0x22, 0x02], 1, Radix::Hex, None, 0).unwrap();
+        assert!(mif.starts_with("WIDTH=8;\nDEPTH=2;\n"));
+        assert!(mif.ends_with("CONTENT BEGIN\n    0 : 22;\n    1 : 02;\nEND;\n"));
+    }
+
+    #[test]
+    fn radixes() {
+        let bytes = vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        let mif = write(bytes.clone(), 6, Radix::Dec, None, 0).unwrap();
+        assert!(mif.contains("DATA_RADIX=UNS;\n"));
+        assert!(mif.contains("    0 : 281474976710655;\n"));
+        let mif = write(bytes, 6, Radix::Bin, None, 0).unwrap();
+        assert!(mif.contains("DATA_RADIX=BIN;\n"));
+        assert!(mif.contains(&format!("    0 : {};\n", "1".repeat(48))));
+        let mif = write(vec![0x0A], 1, Radix::Dec, None, 0).unwrap();
+        assert!(mif.contains("    0 : 10;\n"));
+    }
+}
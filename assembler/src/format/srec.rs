@@ -0,0 +1,161 @@
+use super::{check_address, parse_bytes, to_digits, Records};
+use crate::error::{Error, Errors, Span};
+
+// 1 レコードのデータの最大 byte 数 (S3 のとき)
+pub const MAX_RECORD_LEN: usize = 250;
+
+// Motorola S-record にする (address: 先頭の byte を置くアドレス, record_len: 1 レコードのデータの byte 数)
+// アドレスの幅は最後のアドレスが収まる最小のもの (S1: 16bit, S2: 24bit, S3: 32bit) にする
+// (32bit を超えるアドレスはエラー)
+//
+// S0030000FC
+// S113000022020001000022...
+// S5030003F9
+// S9030000FC
+pub fn write(bytes: &[u8], address: usize, record_len: usize) -> Result<String, Error> {
+    check_address(address, bytes.len())?;
+    let end = address + bytes.len().saturating_sub(1);
+    let (data_kind, end_kind, width) = match end {
+        0..=0xFFFF => (1, 9, 2),
+        0x10000..=0xFF_FFFF => (2, 8, 3),
+        _ => (3, 7, 4),
+    };
+    let record_len = record_len.clamp(1, 255 - width - 1);
+
+    let mut result = record(0, 2, 0, &[]);
+    let mut count = 0;
+    for (idx, chunk) in bytes.chunks(record_len).enumerate() {
+        result += &record(data_kind, width, address + idx * record_len, chunk);
+        count += 1;
+    }
+    result += &match count {
+        0..=0xFFFF => record(5, 2, count, &[]),
+        _ => record(6, 3, count, &[]),
+    };
+    Ok(result + &record(end_kind, width, address, &[]))
+}
+
+fn record(kind: u8, width: usize, address: usize, data: &[u8]) -> String {
+    let mut bytes = vec![(width + data.len() + 1) as u8];
+    bytes.extend(&(address as u32).to_be_bytes()[(4 - width)..]);
+    bytes.extend(data);
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(!sum);
+    format!("S{}{}\n", kind, to_digits(&bytes))
+}
+
+// S-record を (最も小さいアドレス, そこからの byte 列) に戻す
+// (各 byte にはそれが書かれている行を添える. レコードの間は 0 で埋める)
+pub fn parse(text: &str) -> Result<(usize, Vec<(u8, Span)>), Errors> {
+    let mut records = Records::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let span = Span::new(idx + 1, 1, line.len());
+        let kind = line.get(1..2).and_then(|kind| kind.parse::<u8>().ok());
+        let bytes = line.get(2..).and_then(parse_bytes);
+        let (kind, bytes) = match (line.starts_with('S'), kind, bytes) {
+            (true, Some(kind), Some(bytes))
+                if !bytes.is_empty() && bytes.len() == bytes[0] as usize + 1 =>
+            {
+                (kind, bytes)
+            }
+            _ => {
+                records.error(format!("`{}` is not an S-record", line), span);
+                continue;
+            }
+        };
+        let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if sum != 0xFF {
+            records.error("checksum mismatch", span);
+            continue;
+        }
+
+        let width = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => {
+                records.error(format!("unsupported record type S{}", kind), span);
+                continue;
+            }
+        };
+        if bytes.len() < width + 2 {
+            records.error("record is too short", span);
+            continue;
+        }
+        let address = bytes[1..(width + 1)]
+            .iter()
+            .fold(0, |address, byte| (address << 8) | *byte as usize);
+        let data = &bytes[(width + 1)..(bytes.len() - 1)];
+        match kind {
+            1..=3 => records.insert(address, data, span),
+            7..=9 => break,
+            // ヘッダとレコード数は読み飛ばす
+            _ => {}
+        }
+    }
+    records.finish()
+}
+
+// parse から byte 列だけを取り出す
+pub fn read(text: &str) -> Result<(usize, Vec<u8>), Errors> {
+    let (address, bytes) = parse(text)?;
+    Ok((address, bytes.into_iter().map(|(byte, _)| byte).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn round_trip() {
+        let bytes = (0..40).collect::<Vec<u8>>();
+        for (address, kind) in [(0, "S1"), (0x1_0000, "S2"), (0xFFFF_FF00, "S3")] {
+            let text = write(&bytes, address, 16).unwrap();
+            let kinds = text.lines().map(|line| &line[..2]).collect::<Vec<_>>();
+            let end = format!("S{}", 10 - kind[1..].parse::<u8>().unwrap());
+            assert_eq!(kinds, ["S0", kind, kind, kind, "S5", end.as_str()]);
+            assert_eq!(read(&text).unwrap(), (address, bytes.clone()));
+        }
+        assert_eq!(
+            write(&[0x22, 0x02], 0, 16).unwrap(),
+            "S0030000FC\nS10500002202D6\nS5030001FB\nS9030000FC\n"
+        );
+    }
+
+    #[test]
+    fn gap() {
+        let text = [
+            record(1, 2, 0x10, &[1, 2]),
+            record(1, 2, 0x14, &[3]),
+            record(9, 2, 0, &[]),
+        ]
+        .concat();
+        assert_eq!(read(&text).unwrap(), (0x10, vec![1, 2, 0, 0, 3]));
+
+        let text = [record(3, 4, 0, &[1]), record(3, 4, 0xFFF0_0000, &[2])].concat();
+        let errors = parse(&text).unwrap_err();
+        let errors = errors.iter().collect::<Vec<_>>();
+        assert!(
+            matches!(errors[0].kind, ErrorKind::InvalidRecord(_)),
+            "{:?}",
+            errors
+        );
+        assert_eq!(errors[0].span.line, 2);
+    }
+
+    #[test]
+    fn address_too_large() {
+        assert!(write(&[1], 0xFFFF_FFFF, 16).is_ok());
+        let err = write(&[1, 2], 0xFFFF_FFFF, 16).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::AddressTooLarge(0x1_0000_0000)),
+            "{:?}",
+            err
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use super::{addr_width, image_too_large, to_digits, words};
+use crate::error::{Error, ErrorKind};
+
+// $readmemh で読める形式にする (address: 先頭の byte を置くアドレス)
+// 1 行が 1 語 (chunk_size byte) で, 先頭に語単位のアドレスを @ で書く
+// (address が語の境界になければエラー)
+// depth を指定したときは, そこまで 0 で埋める
+//
+// @0
+// 22
+// 02
+pub fn readmemh(
+    bytes: Vec<u8>,
+    chunk_size: usize,
+    address: usize,
+    depth: Option<usize>,
+) -> Result<String, Error> {
+    let words = words(bytes, chunk_size, depth, 0)?;
+    if !address.is_multiple_of(chunk_size) {
+        let kind = ErrorKind::UnalignedAddress {
+            address,
+            chunk_size,
+        };
+        return Err(Error::global(kind));
+    }
+    let mut result = format!("@{:X}\n", address / chunk_size);
+    for word in words {
+        result += &to_digits(&word);
+        result += "\n";
+    }
+    Ok(result)
+}
+
+// 語単位のアドレスを受け取って, その語を返す case 文の ROM モジュールにする
+// (語の幅は chunk_size byte, depth を指定しないときはイメージの語数)
+//
+// module imem (
+//     input  logic [4:0] addr,
+//     output logic [7:0] data
+// );
+//     always_comb begin
+//         case (addr)
+//             5'h00: data = 8'h22;
+//             ...
+pub fn rom_module(
+    bytes: Vec<u8>,
+    chunk_size: usize,
+    depth: Option<usize>,
+    name: &str,
+) -> Result<String, Error> {
+    let words = words(bytes, chunk_size, None, 0)?;
+    let depth = depth.unwrap_or(words.len()).max(1);
+    if words.len() > depth {
+        return Err(image_too_large(words.len(), depth));
+    }
+    let addr_width = addr_width(depth);
+    let data_width = chunk_size * 8;
+
+    let mut result = format!("module {} (\n", name);
+    result += &format!("    input  logic [{}:0] addr,\n", addr_width - 1);
+    result += &format!("    output logic [{}:0] data\n", data_width - 1);
+    result += ");\n";
+    result += "    always_comb begin\n";
+    result += "        case (addr)\n";
+    for (idx, word) in words.iter().enumerate() {
+        result += &format!(
+            "            {}'h{:0digits$X}: data = {}'h{};\n",
+            addr_width,
+            idx,
+            data_width,
+            to_digits(word),
+            digits = addr_width.div_ceil(4)
+        );
+    }
+    result += &format!("            default: data = {}'h0;\n", data_width);
+    result += "        endcase\n";
+    result += "    end\n";
+    result += "endmodule\n";
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readmemh_words() {
+        // 2 byte の語 (上位 byte から), 最後の語の残りは 0
+        let hex = readmemh(vec![0x22, 0x01, 0x0A, 0x02, 0x33], 2, 0, None).unwrap();
+        assert_eq!(hex, "@0\n0122\n020A\n0033\n");
+        // 語単位のアドレスと depth までの 0 埋め
+        let hex = readmemh(vec![0x22, 0x01], 2, 0x20, Some(3)).unwrap();
+        assert_eq!(hex, "@10\n0122\n0000\n0000\n");
+    }
+
+    #[test]
+    fn readmemh_errors() {
+        let err = readmemh(vec![0x22, 0x01], 2, 0x21, None).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            ErrorKind::UnalignedAddress {
+                address: 0x21,
+                chunk_size: 2
+            }
+        ));
+        let err = readmemh(vec![0; 6], 2, 0, Some(2)).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            ErrorKind::ImageTooLarge { words: 3, depth: 2 }
+        ));
+    }
+
+    #[test]
+    fn rom() {
+        let sv = rom_module(vec![0x22, 0x01, 0x0A, 0x02], 2, Some(5), "imem").unwrap();
+        assert_eq!(
+            sv,
+            "module imem (
+    input  logic [2:0] addr,
+    output logic [15:0] data
+);
+    always_comb begin
+        case (addr)
+            3'h0: data = 16'h0122;
+            3'h1: data = 16'h020A;
+            default: data = 16'h0;
+        endcase
+    end
+endmodule
+"
+        );
+        // 空のイメージでもアドレスは 1 bit
+        let sv = rom_module(Vec::new(), 1, None, "dmem").unwrap();
+        assert!(sv.contains("input  logic [0:0] addr"));
+        assert!(sv.contains("default: data = 8'h0;"));
+    }
+}
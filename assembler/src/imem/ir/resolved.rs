@@ -1,3 +1,6 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[rustfmt::skip]
 pub enum Inst {
     Add { rd: u8, rs1: u8, rs2: u8 },
@@ -40,3 +43,51 @@ pub enum Inst {
     Srai { rd: u8, rs1: u8, imm: u32 },
     Slli { rd: u8, rs1: u8, imm: u32 },
 }
+
+// imem::parse が受け付ける書式で出力する (分岐先は相対値)
+impl fmt::Display for Inst {
+    #[rustfmt::skip]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Inst::Add { rd, rs1, rs2 } => write!(f, "add r{} = r{}, r{}", rd, rs1, rs2),
+            Inst::Sub { rd, rs1, rs2 } => write!(f, "sub r{} = r{}, r{}", rd, rs1, rs2),
+
+            Inst::Addi { rd, rs1, imm } => write!(f, "addi r{} = r{}, {}", rd, rs1, imm),
+            Inst::Subi { rd, rs1, imm } => write!(f, "subi r{} = r{}, {}", rd, rs1, imm),
+
+            Inst::Beq { rd, rs1, rs2, imm } => write!(f, "beq r{}, (r{}, r{}) -> {}", rd, rs1, rs2, imm),
+            Inst::Bne { rd, rs1, rs2, imm } => write!(f, "bne r{}, (r{}, r{}) -> {}", rd, rs1, rs2, imm),
+            Inst::Blt { rd, rs1, rs2, imm } => write!(f, "blt r{}, (r{}, r{}) -> {}", rd, rs1, rs2, imm),
+            Inst::Ble { rd, rs1, rs2, imm } => write!(f, "ble r{}, (r{}, r{}) -> {}", rd, rs1, rs2, imm),
+            Inst::Jal { rd, rs1, imm } => write!(f, "jal r{}, r{}[{}]", rd, rs1, imm),
+
+            Inst::Lw { rd, rs1, imm } => write!(f, "lw r{} = r{}[{}]", rd, rs1, imm),
+            Inst::Lh { rd, rs1, imm } => write!(f, "lh r{} = r{}[{}]", rd, rs1, imm),
+            Inst::Lb { rd, rs1, imm } => write!(f, "lb r{} = r{}[{}]", rd, rs1, imm),
+            Inst::Lhu { rd, rs1, imm } => write!(f, "lhu r{} = r{}[{}]", rd, rs1, imm),
+            Inst::Lbu { rd, rs1, imm } => write!(f, "lbu r{} = r{}[{}]", rd, rs1, imm),
+
+            Inst::Sw { rs1, rs2, imm } => write!(f, "sw r{}[{}] = r{}", rs1, imm, rs2),
+            Inst::Sh { rs1, rs2, imm } => write!(f, "sh r{}[{}] = r{}", rs1, imm, rs2),
+            Inst::Sb { rs1, rs2, imm } => write!(f, "sb r{}[{}] = r{}", rs1, imm, rs2),
+            Inst::Isb { rs1, rs2, imm } => write!(f, "isb r{}[{}] = r{}", rs1, imm, rs2),
+
+            Inst::In { rd, rs1, imm } => write!(f, "in r{} = r{}[{}]", rd, rs1, imm),
+            Inst::Out { rs1, rs2, imm } => write!(f, "out r{}[{}] = r{}", rs1, imm, rs2),
+
+            Inst::And { rd, rs1, rs2 } => write!(f, "and r{} = r{}, r{}", rd, rs1, rs2),
+            Inst::Or { rd, rs1, rs2 } => write!(f, "or r{} = r{}, r{}", rd, rs1, rs2),
+            Inst::Xor { rd, rs1, rs2 } => write!(f, "xor r{} = r{}, r{}", rd, rs1, rs2),
+            Inst::Srl { rd, rs1, rs2 } => write!(f, "srl r{} = r{}, r{}", rd, rs1, rs2),
+            Inst::Sra { rd, rs1, rs2 } => write!(f, "sra r{} = r{}, r{}", rd, rs1, rs2),
+            Inst::Sll { rd, rs1, rs2 } => write!(f, "sll r{} = r{}, r{}", rd, rs1, rs2),
+
+            Inst::Andi { rd, rs1, imm } => write!(f, "andi r{} = r{}, {}", rd, rs1, imm),
+            Inst::Ori { rd, rs1, imm } => write!(f, "ori r{} = r{}, {}", rd, rs1, imm),
+            Inst::Xori { rd, rs1, imm } => write!(f, "xori r{} = r{}, {}", rd, rs1, imm),
+            Inst::Srli { rd, rs1, imm } => write!(f, "srli r{} = r{}, {}", rd, rs1, imm),
+            Inst::Srai { rd, rs1, imm } => write!(f, "srai r{} = r{}, {}", rd, rs1, imm),
+            Inst::Slli { rd, rs1, imm } => write!(f, "slli r{} = r{}, {}", rd, rs1, imm),
+        }
+    }
+}
@@ -1,41 +1,86 @@
+use std::fmt;
+
+use crate::error::Span;
+use crate::expr::Expr;
+
 #[derive(Debug)]
 pub struct Inst {
     pub kind: InstKind,
-    pub label: Option<String>,
+    // この命令を指すラベル (続けて書いたものはすべて) とその位置
+    pub labels: Vec<(String, Span)>,
+    pub span: Span,
+    pub arg_spans: ArgSpans,
+    // 擬似命令から展開された場合はその命令名
+    pub pseudo: Option<String>,
+    // マクロの展開で作られた場合は, 呼び出しの位置と引数を置き換えた行
+    pub expansion: Option<(Span, String)>,
+}
+
+impl Inst {
+    // エラーに添える展開結果
+    pub fn note(&self) -> Option<String> {
+        let pseudo = self.pseudo.as_ref()?;
+        Some(format!(
+            "pseudo-instruction `{}` expands to `{}`",
+            pseudo, self.kind
+        ))
+    }
+}
+
+// 各オペランドのソース上の位置 (imm には val も含む)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArgSpans {
+    pub rd: Option<Span>,
+    pub rs1: Option<Span>,
+    pub rs2: Option<Span>,
+    pub imm: Option<Span>,
+}
+
+impl ArgSpans {
+    pub fn get(&self, field: &str) -> Option<Span> {
+        match field {
+            "rd" => self.rd,
+            "rs1" => self.rs1,
+            "rs2" => self.rs2,
+            "imm" | "val" => self.imm,
+            _ => None,
+        }
+    }
 }
 
 // addi rd = rs1, @label
 // addi rd = rs1, $label
 // addi rd = rs1, 0x10
+// addi rd = rs1, $label + 4
 #[derive(Debug)]
 #[rustfmt::skip]
 pub enum InstKind {
     Add { rd: u8, rs1: u8, rs2: u8 },
     Sub { rd: u8, rs1: u8, rs2: u8 },
 
-    Addi { rd: u8, rs1: u8, val: Value },
-    Subi { rd: u8, rs1: u8, val: Value },
-
-    Beq { rd: u8, rs1: u8, rs2: u8, val: Value },
-    Bne { rd: u8, rs1: u8, rs2: u8, val: Value },
-    Blt { rd: u8, rs1: u8, rs2: u8, val: Value },
-    Ble { rd: u8, rs1: u8, rs2: u8, val: Value },
-    Jal { rd: u8, rs1: u8, imm: i32 },
-
-    Lw { rd: u8, rs1: u8, imm: i32 },
-    Lh { rd: u8, rs1: u8, imm: i32 },
-    Lb { rd: u8, rs1: u8, imm: i32 },
-    Lhu { rd: u8, rs1: u8, imm: i32 },
-    Lbu { rd: u8, rs1: u8, imm: i32 },
-
-    Sw { rs1: u8, rs2: u8, imm: i32 },
-    Sh { rs1: u8, rs2: u8, imm: i32 },
-    Sb { rs1: u8, rs2: u8, imm: i32 },
-    Isb { rs1: u8, rs2: u8, imm: i32 },
-
-    In { rd: u8, rs1: u8, imm: i32 },
-    Out { rs1: u8, rs2: u8, imm: i32 },
-    
+    Addi { rd: u8, rs1: u8, val: Expr },
+    Subi { rd: u8, rs1: u8, val: Expr },
+
+    Beq { rd: u8, rs1: u8, rs2: u8, val: Expr },
+    Bne { rd: u8, rs1: u8, rs2: u8, val: Expr },
+    Blt { rd: u8, rs1: u8, rs2: u8, val: Expr },
+    Ble { rd: u8, rs1: u8, rs2: u8, val: Expr },
+    Jal { rd: u8, rs1: u8, imm: Expr },
+
+    Lw { rd: u8, rs1: u8, imm: Expr },
+    Lh { rd: u8, rs1: u8, imm: Expr },
+    Lb { rd: u8, rs1: u8, imm: Expr },
+    Lhu { rd: u8, rs1: u8, imm: Expr },
+    Lbu { rd: u8, rs1: u8, imm: Expr },
+
+    Sw { rs1: u8, rs2: u8, imm: Expr },
+    Sh { rs1: u8, rs2: u8, imm: Expr },
+    Sb { rs1: u8, rs2: u8, imm: Expr },
+    Isb { rs1: u8, rs2: u8, imm: Expr },
+
+    In { rd: u8, rs1: u8, imm: Expr },
+    Out { rs1: u8, rs2: u8, imm: Expr },
+
     And { rd: u8, rs1: u8, rs2: u8 },
     Or { rd: u8, rs1: u8, rs2: u8 },
     Xor { rd: u8, rs1: u8, rs2: u8 },
@@ -43,17 +88,97 @@ pub enum InstKind {
     Sra { rd: u8, rs1: u8, rs2: u8 },
     Sll { rd: u8, rs1: u8, rs2: u8 },
 
-    Andi { rd: u8, rs1: u8, val: Value },
-    Ori { rd: u8, rs1: u8, val: Value },
-    Xori { rd: u8, rs1: u8, val: Value },
-    Srli { rd: u8, rs1: u8, val: Value },
-    Srai { rd: u8, rs1: u8, val: Value },
-    Slli { rd: u8, rs1: u8, val: Value },
+    Andi { rd: u8, rs1: u8, val: Expr },
+    Ori { rd: u8, rs1: u8, val: Expr },
+    Xori { rd: u8, rs1: u8, val: Expr },
+    Srli { rd: u8, rs1: u8, val: Expr },
+    Srai { rd: u8, rs1: u8, val: Expr },
+    Slli { rd: u8, rs1: u8, val: Expr },
 }
 
-#[derive(Debug)]
-pub enum Value {
-    DataLabel(String),
-    InstLabel(String),
-    Imm(i64),
+impl InstKind {
+    // 即値のオペランド (val, imm)
+    #[rustfmt::skip]
+    pub fn expr(&self) -> Option<&Expr> {
+        match self {
+            InstKind::Addi { val, .. } | InstKind::Subi { val, .. } => Some(val),
+            InstKind::Beq { val, .. } | InstKind::Bne { val, .. } => Some(val),
+            InstKind::Blt { val, .. } | InstKind::Ble { val, .. } => Some(val),
+            InstKind::Jal { imm, .. } => Some(imm),
+            InstKind::Lw { imm, .. } | InstKind::Lh { imm, .. } | InstKind::Lb { imm, .. } => Some(imm),
+            InstKind::Lhu { imm, .. } | InstKind::Lbu { imm, .. } => Some(imm),
+            InstKind::Sw { imm, .. } | InstKind::Sh { imm, .. } => Some(imm),
+            InstKind::Sb { imm, .. } | InstKind::Isb { imm, .. } => Some(imm),
+            InstKind::In { imm, .. } | InstKind::Out { imm, .. } => Some(imm),
+            InstKind::Andi { val, .. } | InstKind::Ori { val, .. } | InstKind::Xori { val, .. } => Some(val),
+            InstKind::Srli { val, .. } | InstKind::Srai { val, .. } | InstKind::Slli { val, .. } => Some(val),
+            _ => None,
+        }
+    }
+
+    #[rustfmt::skip]
+    pub fn expr_mut(&mut self) -> Option<&mut Expr> {
+        match self {
+            InstKind::Addi { val, .. } | InstKind::Subi { val, .. } => Some(val),
+            InstKind::Beq { val, .. } | InstKind::Bne { val, .. } => Some(val),
+            InstKind::Blt { val, .. } | InstKind::Ble { val, .. } => Some(val),
+            InstKind::Jal { imm, .. } => Some(imm),
+            InstKind::Lw { imm, .. } | InstKind::Lh { imm, .. } | InstKind::Lb { imm, .. } => Some(imm),
+            InstKind::Lhu { imm, .. } | InstKind::Lbu { imm, .. } => Some(imm),
+            InstKind::Sw { imm, .. } | InstKind::Sh { imm, .. } => Some(imm),
+            InstKind::Sb { imm, .. } | InstKind::Isb { imm, .. } => Some(imm),
+            InstKind::In { imm, .. } | InstKind::Out { imm, .. } => Some(imm),
+            InstKind::Andi { val, .. } | InstKind::Ori { val, .. } | InstKind::Xori { val, .. } => Some(val),
+            InstKind::Srli { val, .. } | InstKind::Srai { val, .. } | InstKind::Slli { val, .. } => Some(val),
+            _ => None,
+        }
+    }
+}
+
+// imem::parse が受け付ける書式で出力する
+impl fmt::Display for InstKind {
+    #[rustfmt::skip]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstKind::Add { rd, rs1, rs2 } => write!(f, "add r{} = r{}, r{}", rd, rs1, rs2),
+            InstKind::Sub { rd, rs1, rs2 } => write!(f, "sub r{} = r{}, r{}", rd, rs1, rs2),
+
+            InstKind::Addi { rd, rs1, val } => write!(f, "addi r{} = r{}, {}", rd, rs1, val),
+            InstKind::Subi { rd, rs1, val } => write!(f, "subi r{} = r{}, {}", rd, rs1, val),
+
+            InstKind::Beq { rd, rs1, rs2, val } => write!(f, "beq r{}, (r{}, r{}) -> {}", rd, rs1, rs2, val),
+            InstKind::Bne { rd, rs1, rs2, val } => write!(f, "bne r{}, (r{}, r{}) -> {}", rd, rs1, rs2, val),
+            InstKind::Blt { rd, rs1, rs2, val } => write!(f, "blt r{}, (r{}, r{}) -> {}", rd, rs1, rs2, val),
+            InstKind::Ble { rd, rs1, rs2, val } => write!(f, "ble r{}, (r{}, r{}) -> {}", rd, rs1, rs2, val),
+            InstKind::Jal { rd, rs1, imm } => write!(f, "jal r{}, r{}[{}]", rd, rs1, imm),
+
+            InstKind::Lw { rd, rs1, imm } => write!(f, "lw r{} = r{}[{}]", rd, rs1, imm),
+            InstKind::Lh { rd, rs1, imm } => write!(f, "lh r{} = r{}[{}]", rd, rs1, imm),
+            InstKind::Lb { rd, rs1, imm } => write!(f, "lb r{} = r{}[{}]", rd, rs1, imm),
+            InstKind::Lhu { rd, rs1, imm } => write!(f, "lhu r{} = r{}[{}]", rd, rs1, imm),
+            InstKind::Lbu { rd, rs1, imm } => write!(f, "lbu r{} = r{}[{}]", rd, rs1, imm),
+
+            InstKind::Sw { rs1, rs2, imm } => write!(f, "sw r{}[{}] = r{}", rs1, imm, rs2),
+            InstKind::Sh { rs1, rs2, imm } => write!(f, "sh r{}[{}] = r{}", rs1, imm, rs2),
+            InstKind::Sb { rs1, rs2, imm } => write!(f, "sb r{}[{}] = r{}", rs1, imm, rs2),
+            InstKind::Isb { rs1, rs2, imm } => write!(f, "isb r{}[{}] = r{}", rs1, imm, rs2),
+
+            InstKind::In { rd, rs1, imm } => write!(f, "in r{} = r{}[{}]", rd, rs1, imm),
+            InstKind::Out { rs1, rs2, imm } => write!(f, "out r{}[{}] = r{}", rs1, imm, rs2),
+
+            InstKind::And { rd, rs1, rs2 } => write!(f, "and r{} = r{}, r{}", rd, rs1, rs2),
+            InstKind::Or { rd, rs1, rs2 } => write!(f, "or r{} = r{}, r{}", rd, rs1, rs2),
+            InstKind::Xor { rd, rs1, rs2 } => write!(f, "xor r{} = r{}, r{}", rd, rs1, rs2),
+            InstKind::Srl { rd, rs1, rs2 } => write!(f, "srl r{} = r{}, r{}", rd, rs1, rs2),
+            InstKind::Sra { rd, rs1, rs2 } => write!(f, "sra r{} = r{}, r{}", rd, rs1, rs2),
+            InstKind::Sll { rd, rs1, rs2 } => write!(f, "sll r{} = r{}, r{}", rd, rs1, rs2),
+
+            InstKind::Andi { rd, rs1, val } => write!(f, "andi r{} = r{}, {}", rd, rs1, val),
+            InstKind::Ori { rd, rs1, val } => write!(f, "ori r{} = r{}, {}", rd, rs1, val),
+            InstKind::Xori { rd, rs1, val } => write!(f, "xori r{} = r{}, {}", rd, rs1, val),
+            InstKind::Srli { rd, rs1, val } => write!(f, "srli r{} = r{}, {}", rd, rs1, val),
+            InstKind::Srai { rd, rs1, val } => write!(f, "srai r{} = r{}, {}", rd, rs1, val),
+            InstKind::Slli { rd, rs1, val } => write!(f, "slli r{} = r{}, {}", rd, rs1, val),
+        }
+    }
 }
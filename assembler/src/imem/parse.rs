@@ -1,344 +1,379 @@
+use crate::error::{Error, ErrorKind, Errors, Span};
+use crate::expr::Expr;
+use crate::imem::ir::unresolved::ArgSpans;
 use crate::imem::ir::unresolved::Inst;
 use crate::imem::ir::unresolved::InstKind;
-use crate::imem::ir::unresolved::Value;
+use crate::lex::{Line, Token, TokenKind};
+use crate::syntax::Parser;
 
-pub fn parse(lines: &[&str]) -> anyhow::Result<Vec<Inst>> {
+// 続けて書いたラベルはすべて次の命令を指す
+// 後に命令のないラベルは (命令部の終わりを指すものとして) 別に返す
+pub fn parse(lines: &[Line], errors: &mut Errors) -> (Vec<Inst>, Vec<(String, Span)>) {
     // program
     // 1: addi r1 = r0, 1\n
     // 2: beq r0, (r0, r0) -> -42\n
     // 3: lw r7 = r0[4]\n
     // 4: ...
 
-    let lines = lines
-        .into_iter()
-        .map(|line| line.trim())
-        .map(|line| line.split("//").collect::<Vec<_>>()[0])
-        .filter(|line| !line.is_empty())
-        .collect::<Vec<&str>>();
-
     let mut insts = Vec::new();
-    let mut label = None;
+    let mut labels = Vec::new();
     for line in lines {
+        let mut parser = Parser::new(line);
+
         // label
-        if line.starts_with("@") {
-            label = Some(line[1..].to_string());
-        } else {
-            let mut inst = parse_line(line)?;
-            if label.is_some() {
-                inst.label = label.take();
+        if let Some(Token {
+            kind: TokenKind::InstLabel(name),
+            span,
+        }) = parser.peek()
+        {
+            labels.push((name.clone(), *span));
+            parser.bump();
+            if parser.is_end() {
+                continue;
+            }
+        }
+
+        // エラーの行は読み飛ばして次の行から再開する
+        match parse_inst(&mut parser) {
+            Ok(mut line_insts) => {
+                if let Some(first) = line_insts.first_mut() {
+                    first.labels = std::mem::take(&mut labels);
+                }
+                for inst in &mut line_insts {
+                    inst.expansion = line.expansion.clone();
+                }
+                insts.extend(line_insts)
             }
-            insts.push(inst)
+            Err(err) => errors.push(err),
         }
     }
-    Ok(insts)
+    (insts, labels)
 }
 
-fn parse_line(line: &str) -> anyhow::Result<Inst> {
-    let (lhs, rhs) = if line.contains("->") {
-        // beq, ...
-        let splitted_by_arrow = line.split("->").collect::<Vec<_>>();
-        (splitted_by_arrow[0], splitted_by_arrow[1])
-    } else if line.contains("=") {
-        // add, lw, sw, in, out, ...
-        let splitted_by_eq = line.split("=").collect::<Vec<_>>();
-        (splitted_by_eq[0], splitted_by_eq[1])
-    } else {
-        // jal
-        let splitted_by_eq = line.split(",").collect::<Vec<_>>();
-        (splitted_by_eq[0], splitted_by_eq[1])
-    };
-    let lhs = lhs.split_ascii_whitespace().collect::<Vec<_>>();
-    let (kind, lhs) = (lhs[0], lhs[1..].concat());
-
-    // beq
-    if lhs.contains("(") {
-        let rhs = rhs.trim();
-        let lhs = lhs.split(",").map(|e| e.trim()).collect::<Vec<_>>();
-        // beq r0, (r0, r0) -> -42
-        // beq | r0, (r0, r0) | -42
-        // beq | r0, (r0, r0) | -42
-        // beq | r0 | (r0 | r0) | -42
-        return parse_inst(
-            kind,
-            vec![
-                lhs[0],
-                &lhs[1].replace("(", ""),
-                &lhs[2].replace(")", ""),
-                rhs,
-            ],
-        );
-    }
+// オペランドの書式
+enum Format {
+    R, // add rd = rs1, rs2
+    I, // addi rd = rs1, val
+    B, // beq rd, (rs1, rs2) -> val
+    J, // jal rd, rs1[imm]
+    L, // lw rd = rs1[imm]
+    S, // sw rs1[imm] = rs2
+
+    // 擬似命令
+    N, // nop
+    M, // mv rd = rs1
+    V, // li rd = val
+    T, // j val
+}
+
+#[rustfmt::skip]
+fn format_of(kind: &str) -> Option<Format> {
+    match kind {
+        "add" | "sub" | "and" | "or" | "xor" | "srl" | "sra" | "sll" => Some(Format::R),
+        "addi" | "subi" | "andi" | "ori" | "xori" | "srli" | "srai" | "slli" => Some(Format::I),
+        "beq" | "bne" | "blt" | "ble" => Some(Format::B),
+        "jal" => Some(Format::J),
+        "lw" | "lh" | "lb" | "lhu" | "lbu" | "in" => Some(Format::L),
+        "sw" | "sh" | "sb" | "isb" | "out" => Some(Format::S),
 
-    // sw, ..., out
-    if lhs.contains("[") {
-        let rhs = rhs.trim();
-        let lhs = lhs.split("[").map(|e| e.trim()).collect::<Vec<_>>();
-        // sw r0[4] = r7
-        // sw r0[4] | r7
-        // sw r0 | 4] | r7
-        return parse_inst(kind, vec![lhs[0], &lhs[1].replace("]", ""), rhs]);
+        "nop" | "ret" => Some(Format::N),
+        "mv" | "not" | "neg" => Some(Format::M),
+        "li" | "la" => Some(Format::V),
+        "j" | "call" => Some(Format::T),
+        "bgt" | "bge" => Some(Format::B),
+        _ => None,
     }
+}
+
+struct Args {
+    rd: u8,
+    rs1: u8,
+    rs2: u8,
+    val: Expr,
+    imm: Expr,
+    spans: ArgSpans,
+}
+
+// 擬似命令は複数の命令に展開されることがある
+fn parse_inst(parser: &mut Parser) -> Result<Vec<Inst>, Error> {
+    let (kind, kind_span) = parser.expect_ident()?;
+    let format = format_of(&kind)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInstruction(kind.clone()), kind_span))?;
 
-    // lw, ..., in
-    if rhs.contains("[") {
-        let lhs = lhs.trim();
-        let rhs = rhs.split("[").map(|e| e.trim()).collect::<Vec<_>>();
-        // lw r7 = r0[4]
-        // lw | r7 | r0[4]
-        // lw | r7 | r0 | 4]
-        return parse_inst(kind, vec![lhs, rhs[0], &rhs[1].replace("]", "")]);
+    let mut args = Args {
+        rd: 0,
+        rs1: 0,
+        rs2: 0,
+        val: Expr::Num(0),
+        imm: Expr::Num(0),
+        spans: ArgSpans::default(),
+    };
+    match format {
+        Format::R => {
+            (args.rd, args.spans.rd) = parse_reg(parser)?;
+            parser.expect_punct("=")?;
+            (args.rs1, args.spans.rs1) = parse_reg(parser)?;
+            parser.expect_punct(",")?;
+            (args.rs2, args.spans.rs2) = parse_reg(parser)?;
+        }
+        Format::I => {
+            (args.rd, args.spans.rd) = parse_reg(parser)?;
+            parser.expect_punct("=")?;
+            (args.rs1, args.spans.rs1) = parse_reg(parser)?;
+            parser.expect_punct(",")?;
+            (args.val, args.spans.imm) = parse_value(parser)?;
+        }
+        Format::B => {
+            (args.rd, args.spans.rd) = parse_reg(parser)?;
+            parser.expect_punct(",")?;
+            parser.expect_punct("(")?;
+            (args.rs1, args.spans.rs1) = parse_reg(parser)?;
+            parser.expect_punct(",")?;
+            (args.rs2, args.spans.rs2) = parse_reg(parser)?;
+            parser.expect_punct(")")?;
+            parser.expect_punct("->")?;
+            (args.val, args.spans.imm) = parse_value(parser)?;
+        }
+        Format::J => {
+            (args.rd, args.spans.rd) = parse_reg(parser)?;
+            parser.expect_punct(",")?;
+            (args.rs1, args.spans.rs1) = parse_reg(parser)?;
+            (args.imm, args.spans.imm) = parse_offset(parser)?;
+        }
+        Format::L => {
+            (args.rd, args.spans.rd) = parse_reg(parser)?;
+            parser.expect_punct("=")?;
+            (args.rs1, args.spans.rs1) = parse_reg(parser)?;
+            (args.imm, args.spans.imm) = parse_offset(parser)?;
+        }
+        Format::S => {
+            (args.rs1, args.spans.rs1) = parse_reg(parser)?;
+            (args.imm, args.spans.imm) = parse_offset(parser)?;
+            parser.expect_punct("=")?;
+            (args.rs2, args.spans.rs2) = parse_reg(parser)?;
+        }
+        Format::N => {}
+        Format::M => {
+            (args.rd, args.spans.rd) = parse_reg(parser)?;
+            expect_assign(parser)?;
+            (args.rs1, args.spans.rs1) = parse_reg(parser)?;
+        }
+        Format::V => {
+            (args.rd, args.spans.rd) = parse_reg(parser)?;
+            expect_assign(parser)?;
+            (args.val, args.spans.imm) = parse_value(parser)?;
+        }
+        Format::T => {
+            (args.val, args.spans.imm) = parse_value(parser)?;
+        }
     }
+    parser.expect_end()?;
 
-    // jal
-    if lhs.starts_with("jal") {
-        // jal r0, r1[0]
-        // lhs: jal r0
-        // rhs: r1[0]
-        // save_reg: r0
-        let save_reg = lhs.trim().split_ascii_whitespace().next().unwrap().trim();
-        let rhs = rhs.split("[").map(|e| e.trim()).collect::<Vec<_>>();
-        return parse_inst("jal", vec![save_reg, rhs[0], &rhs[1].replace("]", "")]);
+    let span = kind_span.to(parser.prev_span());
+
+    // 擬似命令
+    if let Some(expansion) = expand_pseudo(&kind, &args) {
+        return Ok(expansion
+            .into_iter()
+            .map(|(inst_kind, arg_spans)| Inst {
+                kind: inst_kind,
+                labels: Vec::new(),
+                span,
+                arg_spans,
+                pseudo: Some(kind.clone()),
+                expansion: None,
+            })
+            .collect());
     }
 
-    // add, addi, ...
-    let lhs = lhs.trim();
-    let rhs = rhs.split(",").map(|e| e.trim()).collect::<Vec<_>>();
+    let Args {
+        rd,
+        rs1,
+        rs2,
+        val,
+        imm,
+        spans,
+    } = args;
+    #[rustfmt::skip]
+    let inst_kind = match kind.as_str() {
+        "add" => InstKind::Add { rd, rs1, rs2 },
+        "sub" => InstKind::Sub { rd, rs1, rs2 },
+
+        "addi" => InstKind::Addi { rd, rs1, val },
+        "subi" => InstKind::Subi { rd, rs1, val },
+
+        "beq" => InstKind::Beq { rd, rs1, rs2, val },
+        "bne" => InstKind::Bne { rd, rs1, rs2, val },
+        "blt" => InstKind::Blt { rd, rs1, rs2, val },
+        "ble" => InstKind::Ble { rd, rs1, rs2, val },
+        "jal" => InstKind::Jal { rd, rs1, imm },
+
+        "lw" => InstKind::Lw { rd, rs1, imm },
+        "lh" => InstKind::Lh { rd, rs1, imm },
+        "lb" => InstKind::Lb { rd, rs1, imm },
+        "lhu" => InstKind::Lhu { rd, rs1, imm },
+        "lbu" => InstKind::Lbu { rd, rs1, imm },
 
-    parse_inst(kind, vec![lhs, rhs[0], rhs[1]])
+        "sw" => InstKind::Sw { rs1, rs2, imm },
+        "sh" => InstKind::Sh { rs1, rs2, imm },
+        "sb" => InstKind::Sb { rs1, rs2, imm },
+        "isb" => InstKind::Isb { rs1, rs2, imm },
+
+        "in" => InstKind::In { rd, rs1, imm },
+        "out" => InstKind::Out { rs1, rs2, imm },
+
+        "and" => InstKind::And { rd, rs1, rs2 },
+        "or" => InstKind::Or { rd, rs1, rs2 },
+        "xor" => InstKind::Xor { rd, rs1, rs2 },
+        "srl" => InstKind::Srl { rd, rs1, rs2 },
+        "sra" => InstKind::Sra { rd, rs1, rs2 },
+        "sll" => InstKind::Sll { rd, rs1, rs2 },
+
+        "andi" => InstKind::Andi { rd, rs1, val },
+        "ori" => InstKind::Ori { rd, rs1, val },
+        "xori" => InstKind::Xori { rd, rs1, val },
+        "srli" => InstKind::Srli { rd, rs1, val },
+        "srai" => InstKind::Srai { rd, rs1, val },
+        "slli" => InstKind::Slli { rd, rs1, val },
+
+        _ => return Err(Error::new(ErrorKind::InvalidInstruction(kind), kind_span)),
+    };
+
+    Ok(vec![Inst {
+        kind: inst_kind,
+        labels: Vec::new(),
+        span,
+        arg_spans: spans,
+        pseudo: None,
+        expansion: None,
+    }])
+}
+
+// 擬似命令を命令列に展開する (擬似命令でなければ None)
+// (呼び出しの戻り先は r1 に入れる)
+fn expand_pseudo(kind: &str, args: &Args) -> Option<Vec<(InstKind, ArgSpans)>> {
+    let Args {
+        rd,
+        rs1,
+        rs2,
+        ref val,
+        spans,
+        ..
+    } = *args;
+    let val = val.clone();
+    let none = ArgSpans::default();
+    // rs1 と rs2 を入れ替えた位置
+    let swapped = ArgSpans {
+        rs1: spans.rs2,
+        rs2: spans.rs1,
+        ..spans
+    };
+
+    #[rustfmt::skip]
+    let expansion = match kind {
+        "nop" => vec![(InstKind::Add { rd: 0, rs1: 0, rs2: 0 }, none)],
+        "mv" => vec![(InstKind::Add { rd, rs1, rs2: 0 }, spans)],
+        "not" => vec![(InstKind::Xori { rd, rs1, val: Expr::Num(-1) }, spans)],
+        "neg" => vec![(InstKind::Sub { rd, rs1: 0, rs2: rs1 }, ArgSpans { rd: spans.rd, rs2: spans.rs1, ..none })],
+        "li" | "la" => vec![(InstKind::Addi { rd, rs1: 0, val }, spans)],
+        "j" => vec![(InstKind::Beq { rd: 0, rs1: 0, rs2: 0, val }, spans)],
+        "call" => vec![(InstKind::Beq { rd: 1, rs1: 0, rs2: 0, val }, spans)],
+        "ret" => vec![(InstKind::Jal { rd: 0, rs1: 1, imm: Expr::Num(0) }, none)],
+        "bgt" => vec![(InstKind::Blt { rd, rs1: rs2, rs2: rs1, val }, swapped)],
+        "bge" => vec![(InstKind::Ble { rd, rs1: rs2, rs2: rs1, val }, swapped)],
+        _ => return None,
+    };
+    Some(expansion)
 }
 
-fn parse_inst(kind: &str, args: Vec<&str>) -> anyhow::Result<Inst> {
-    enum ArgEither {
-        Num(i64),
-        String(String),
+// mv rd = rs と mv rd, rs のどちらの形も受け付ける (mv, not, neg, li, la)
+fn expect_assign(parser: &mut Parser) -> Result<(), Error> {
+    if parser.eat_punct("=") || parser.eat_punct(",") {
+        Ok(())
+    } else {
+        Err(parser.unexpected("\"=\" or \",\""))
     }
+}
 
-    impl ArgEither {
-        fn u8(&self) -> u8 {
-            match self {
-                ArgEither::Num(num) => *num as u8,
-                ArgEither::String(s) => panic!("Unexpected string: {}", s),
-            }
-        }
+fn parse_reg(parser: &mut Parser) -> Result<(u8, Option<Span>), Error> {
+    let (reg, span) = parser.expect_reg()?;
+    Ok((reg, Some(span)))
+}
 
-        fn i32(&self) -> i32 {
-            match self {
-                ArgEither::Num(num) => *num as i32,
-                ArgEither::String(s) => panic!("Unexpected string: {}", s),
-            }
-        }
+// 0x10, $label, @label, $label + 4, ...
+fn parse_value(parser: &mut Parser) -> Result<(Expr, Option<Span>), Error> {
+    let (expr, span) = parser.expect_expr()?;
+    Ok((expr, Some(span)))
+}
 
-        fn value(&self) -> Value {
-            match self {
-                ArgEither::Num(num) => Value::Imm(*num as i64),
-                ArgEither::String(s) => {
-                    let mut chars = s.chars();
-                    let first_c = chars.next().unwrap();
-                    match first_c {
-                        '$' => Value::DataLabel(chars.collect::<String>()),
-                        '@' => Value::InstLabel(chars.collect::<String>()),
-                        _ => panic!("Unexpected label: {}", s),
-                    }
-                }
-            }
+// [imm]
+fn parse_offset(parser: &mut Parser) -> Result<(Expr, Option<Span>), Error> {
+    parser.expect_punct("[")?;
+    let (expr, span) = parser.expect_expr()?;
+    parser.expect_punct("]")?;
+    Ok((expr, Some(span)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Errors;
+    use crate::{assemble, read_hex};
+
+    // 命令語 (下位 byte から 6 byte)
+    fn words(source: &str) -> Vec<u64> {
+        let (_, inst) = assemble(source, 6).unwrap();
+        let bytes = read_hex(&inst, 6).unwrap();
+        bytes
+            .chunks(6)
+            .map(|word| {
+                word.iter()
+                    .rev()
+                    .fold(0, |word, byte| (word << 8) | *byte as u64)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pseudo_instructions() {
+        #[rustfmt::skip]
+        let table: &[(&str, &str, u64)] = &[
+            ("nop", "add r0 = r0, r0", 0x0000_0000_0021),
+            ("mv r3 = r4", "add r3 = r4, r0", 0x0000_0000_8321),
+            ("mv r3, r4", "add r3 = r4, r0", 0x0000_0000_8321),
+            ("not r5 = r6", "xori r5 = r6, -1", 0xFFFF_FFFF_C548),
+            ("neg r7 = r8", "sub r7 = r0, r8", 0x0000_0020_0741),
+            ("li r9 = -2", "addi r9 = r0, -2", 0xFFFF_FFFE_0922),
+            ("li r9, 0x12345678", "addi r9 = r0, 0x12345678", 0x1234_5678_0922),
+            ("la r10 = $d", "addi r10 = r0, $d", 0x0000_0001_0A22),
+            ("j @x", "beq r0, (r0, r0) -> @x", 0x0000_0000_0003),
+            ("call @x", "beq r1, (r0, r0) -> @x", 0x0000_0000_0103),
+            ("ret", "jal r0, r1[0]", 0x0000_0000_2083),
+            ("bgt r11, (r12, r13) -> @x", "blt r11, (r13, r12) -> @x", 0x0000_0031_AB43),
+            ("bge r11, (r12, r13) -> @x", "ble r11, (r13, r12) -> @x", 0x0000_0031_AB63),
+        ];
+        for (pseudo, expansion, word) in table {
+            let program = |inst: &str| format!(".data\n    byte1 0\n$d\n.text\n@x\n    {}\n", inst);
+            assert_eq!(words(&program(pseudo)), [*word], "{}", pseudo);
+            assert_eq!(words(&program(expansion)), [*word], "{}", expansion);
         }
     }
 
-    let args = args
-        .into_iter()
-        .map(|arg| {
-            let num = if (arg.starts_with("0x") || arg.starts_with("0X")) && arg.len() > 2 {
-                i64::from_str_radix(&arg[2..], 16)
-            } else if (arg.starts_with("0b") || arg.starts_with("0B")) && arg.len() > 2 {
-                i64::from_str_radix(&arg[2..], 2)
-            } else {
-                // レジスタの指定を数値として扱いたい(r0, r1, ..., r31)
-                i64::from_str_radix(&arg.replace("r", ""), 10)
-            };
-            match num {
-                Ok(num) => ArgEither::Num(num),
-                Err(_) => ArgEither::String(arg.to_string()),
-            }
-        })
-        .collect::<Vec<_>>();
-
-    let inst_kind = match kind {
-        "add" => Ok(InstKind::Add {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-        }),
-        "sub" => Ok(InstKind::Sub {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-        }),
-
-        "addi" => Ok(InstKind::Addi {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            val: args[2].value(),
-        }),
-        "subi" => Ok(InstKind::Subi {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            val: args[2].value(),
-        }),
-
-        "beq" => Ok(InstKind::Beq {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-            val: args[3].value(),
-        }),
-        "bne" => Ok(InstKind::Bne {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-            val: args[3].value(),
-        }),
-        "blt" => Ok(InstKind::Blt {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-            val: args[3].value(),
-        }),
-        "ble" => Ok(InstKind::Ble {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-            val: args[3].value(),
-        }),
-        "jal" => Ok(InstKind::Jal {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            imm: args[2].i32(),
-        }),
-
-        "lw" => Ok(InstKind::Lw {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            imm: args[2].i32(),
-        }),
-        "lh" => Ok(InstKind::Lh {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            imm: args[2].i32(),
-        }),
-        "lb" => Ok(InstKind::Lb {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            imm: args[2].i32(),
-        }),
-        "lhu" => Ok(InstKind::Lhu {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            imm: args[2].i32(),
-        }),
-        "lbu" => Ok(InstKind::Lbu {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            imm: args[2].i32(),
-        }),
-
-        "sw" => Ok(InstKind::Sw {
-            rs1: args[0].u8(),
-            imm: args[1].i32(),
-            rs2: args[2].u8(),
-        }),
-        "sh" => Ok(InstKind::Sh {
-            rs1: args[0].u8(),
-            imm: args[1].i32(),
-            rs2: args[2].u8(),
-        }),
-        "sb" => Ok(InstKind::Sb {
-            rs1: args[0].u8(),
-            imm: args[1].i32(),
-            rs2: args[2].u8(),
-        }),
-        "isb" => Ok(InstKind::Isb {
-            rs1: args[0].u8(),
-            imm: args[1].i32(),
-            rs2: args[2].u8(),
-        }),
-        "in" => Ok(InstKind::In {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            imm: args[2].i32(),
-        }),
-        "out" => Ok(InstKind::Out {
-            rs1: args[0].u8(),
-            imm: args[1].i32(),
-            rs2: args[2].u8(),
-        }),
-
-        "and" => Ok(InstKind::And {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-        }),
-        "or" => Ok(InstKind::Or {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-        }),
-        "xor" => Ok(InstKind::Xor {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-        }),
-        "srl" => Ok(InstKind::Srl {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-        }),
-        "sra" => Ok(InstKind::Sra {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-        }),
-        "sll" => Ok(InstKind::Sll {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            rs2: args[2].u8(),
-        }),
-
-        "andi" => Ok(InstKind::Andi {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            val: args[2].value(),
-        }),
-        "ori" => Ok(InstKind::Ori {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            val: args[2].value(),
-        }),
-        "xori" => Ok(InstKind::Xori {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            val: args[2].value(),
-        }),
-        "srli" => Ok(InstKind::Srli {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            val: args[2].value(),
-        }),
-        "srai" => Ok(InstKind::Srai {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            val: args[2].value(),
-        }),
-        "slli" => Ok(InstKind::Slli {
-            rd: args[0].u8(),
-            rs1: args[1].u8(),
-            val: args[2].value(),
-        }),
-
-        _ => Err(anyhow::anyhow!("Invalid instruction: {}", kind)),
-    }?;
-
-    Ok(Inst {
-        kind: inst_kind,
-        label: None,
-    })
+    #[test]
+    fn pseudo_errors() {
+        // bgt, bge のエラーは入れ替えた後のレジスタでなく, 書いた位置を指す
+        let err = assemble(".text\n@x\n    bgt r0, (r1, r40) -> @x\n", 6).unwrap_err();
+        let errors = err.downcast::<Errors>().unwrap();
+        let errors = errors.iter().collect::<Vec<_>>();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].span.line, errors[0].span.col), (3, 18));
+        assert_eq!(
+            errors[0].note.as_deref(),
+            Some("pseudo-instruction `bgt` expands to `blt r0, (r40, r1) -> @x`")
+        );
+
+        let err = assemble(".text\n    mv r3 r4\n", 6).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Unexpected token(expect: \"=\" or \",\"): r4"));
+    }
 }
@@ -0,0 +1,206 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, ErrorKind, Errors, Span};
+use crate::lex::{lex, Line, Token, TokenKind};
+use crate::source::{normalize, FileLoader, SourceMap};
+use crate::syntax::Parser;
+
+// file を字句解析し, include "path" の行をそのファイルの内容で置き換える
+// (path は include を書いたファイルからの相対パス)
+pub fn lex_file(
+    sources: &mut SourceMap,
+    loader: &dyn FileLoader,
+    file: usize,
+    errors: &mut Errors,
+) -> Vec<Line> {
+    let mut lines = Vec::new();
+    lex_into(sources, loader, file, &mut Vec::new(), &mut lines, errors);
+    lines
+}
+
+// stack は include 中のファイル (循環の検出用)
+fn lex_into(
+    sources: &mut SourceMap,
+    loader: &dyn FileLoader,
+    file: usize,
+    stack: &mut Vec<PathBuf>,
+    lines: &mut Vec<Line>,
+    errors: &mut Errors,
+) {
+    let Some(source_file) = sources.get(file) else {
+        return;
+    };
+    let path = normalize(&source_file.path);
+    let file_lines = lex(&source_file.source, file, errors);
+
+    stack.push(path.clone());
+    for line in file_lines {
+        let (target, span) = match parse_include(&line) {
+            Some(Ok(include)) => include,
+            Some(Err(err)) => {
+                errors.push(err);
+                continue;
+            }
+            None => {
+                lines.push(line);
+                continue;
+            }
+        };
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let target_path = normalize(&dir.join(&target));
+        if let Some(pos) = stack.iter().position(|p| *p == target_path) {
+            let cycle = stack[pos..]
+                .iter()
+                .chain([&target_path])
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            errors.push(Error::new(ErrorKind::IncludeCycle(cycle), span));
+            continue;
+        }
+
+        match sources.include(loader, &target_path, file) {
+            Ok(included) => lex_into(sources, loader, included, stack, lines, errors),
+            Err(err) => errors.push(Error::new(
+                ErrorKind::CannotRead {
+                    path: target,
+                    reason: err.to_string(),
+                },
+                span,
+            )),
+        }
+    }
+    stack.pop();
+}
+
+// include "path"
+fn parse_include(line: &Line) -> Option<Result<(String, Span), Error>> {
+    match line.tokens.first() {
+        Some(Token {
+            kind: TokenKind::Ident(ident),
+            ..
+        }) if ident == "include" => {}
+        _ => return None,
+    }
+
+    let mut parser = Parser::new(line);
+    let result = parser.expect_ident().and_then(|_| {
+        let (path, span) = parser.expect_str()?;
+        parser.expect_end()?;
+        Ok((path, span))
+    });
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use super::lex_file;
+    use crate::error::{ErrorKind, Errors};
+    use crate::lex::{Line, TokenKind};
+    use crate::source::SourceMap;
+
+    fn lex_files(files: &[(&str, &str)]) -> (Vec<Line>, SourceMap, Errors) {
+        let loader = files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect::<HashMap<_, _>>();
+        let mut sources = SourceMap::new();
+        let main = sources.load(&loader, Path::new(files[0].0)).unwrap();
+        let mut errors = Errors::new(None);
+        let lines = lex_file(&mut sources, &loader, main, &mut errors);
+        (lines, sources, errors)
+    }
+
+    // 各行の最初の識別子と, それを書いたファイル
+    fn idents(lines: &[Line], sources: &SourceMap) -> Vec<(String, PathBuf)> {
+        lines
+            .iter()
+            .filter_map(|line| match &line.tokens.first()?.kind {
+                TokenKind::Ident(ident) => {
+                    let path = sources.get(line.eol.file)?.path.clone();
+                    Some((ident.clone(), path))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nested_include() {
+        let (lines, sources, errors) = lex_files(&[
+            ("main.asm", "first\ninclude \"lib/util.asm\"\nlast\n"),
+            (
+                "lib/util.asm",
+                "util\ninclude \"../common.asm\"\ninclude \"defs.asm\"\n",
+            ),
+            ("lib/defs.asm", "defs\n"),
+            ("common.asm", "common\n"),
+        ]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let path = PathBuf::from;
+        assert_eq!(
+            idents(&lines, &sources),
+            [
+                ("first".to_string(), path("main.asm")),
+                ("util".to_string(), path("lib/util.asm")),
+                ("common".to_string(), path("common.asm")),
+                ("defs".to_string(), path("lib/defs.asm")),
+                ("last".to_string(), path("main.asm")),
+            ]
+        );
+    }
+
+    #[test]
+    fn same_file_twice() {
+        let (lines, sources, errors) = lex_files(&[
+            ("main.asm", "include \"defs.asm\"\ninclude \"./defs.asm\"\n"),
+            ("defs.asm", "defs\n"),
+        ]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(idents(&lines, &sources).len(), 2);
+    }
+
+    #[test]
+    fn include_cycle() {
+        let (lines, sources, errors) = lex_files(&[
+            ("main.asm", "main\ninclude \"a.asm\"\n"),
+            ("a.asm", "a\ninclude \"sub/b.asm\"\n"),
+            ("sub/b.asm", "b\ninclude \"../a.asm\"\n"),
+        ]);
+        let kinds = errors.iter().map(|err| &err.kind).collect::<Vec<_>>();
+        match kinds[..] {
+            [ErrorKind::IncludeCycle(cycle)] => {
+                assert_eq!(cycle, "a.asm -> sub/b.asm -> a.asm");
+            }
+            _ => panic!("{:?}", kinds),
+        }
+        assert_eq!(idents(&lines, &sources).len(), 3);
+
+        let (_, _, errors) = lex_files(&[("main.asm", "include \"main.asm\"\n")]);
+        let kinds = errors.iter().map(|err| &err.kind).collect::<Vec<_>>();
+        assert!(
+            matches!(kinds[..], [ErrorKind::IncludeCycle(_)]),
+            "{:?}",
+            kinds
+        );
+    }
+
+    #[test]
+    fn missing_file() {
+        let (_, _, errors) = lex_files(&[("main.asm", "include \"none.asm\"\n")]);
+        let errors = errors.iter().collect::<Vec<_>>();
+        match &errors[..] {
+            [err] => {
+                assert!(
+                    matches!(&err.kind, ErrorKind::CannotRead { path, .. } if path == "none.asm")
+                );
+                assert_eq!((err.span.line, err.span.col), (1, 9));
+            }
+            _ => panic!("{:?}", errors),
+        }
+    }
+}
@@ -0,0 +1,419 @@
+use std::fmt;
+
+use crate::error::{Error, ErrorKind, Errors, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Ident(String),
+    Reg(u8),
+    Num(i64),
+    DataLabel(String),
+    InstLabel(String),
+    Str(String),
+    Char(char),
+    Punct(&'static str),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Ident(ident) => write!(f, "{}", ident),
+            TokenKind::Reg(reg) => write!(f, "r{}", reg),
+            TokenKind::Num(num) => write!(f, "{}", num),
+            TokenKind::DataLabel(label) => write!(f, "${}", label),
+            TokenKind::InstLabel(label) => write!(f, "@{}", label),
+            TokenKind::Str(s) => write!(f, "{:?}", s),
+            TokenKind::Char(c) => write!(f, "{:?}", c),
+            TokenKind::Punct(p) => write!(f, "{}", p),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+// 1 行分のトークン列 (eol は行末の位置)
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub tokens: Vec<Token>,
+    pub eol: Span,
+    // マクロの展開で作られた行は, 呼び出した (ソースに書かれた) 行の位置と, 引数を置き換えた行
+    pub expansion: Option<(Span, String)>,
+}
+
+// トークン列をソースに書く形の文字列にする (sw r2[0] = r3)
+pub fn render(tokens: &[Token]) -> String {
+    let mut result = String::new();
+    let mut prev: Option<&TokenKind> = None;
+    // 直前のトークンが単項演算子か
+    let mut unary = false;
+    for token in tokens {
+        let space = match (prev, &token.kind) {
+            (None, _) => false,
+            _ if unary => false,
+            (_, TokenKind::Punct("," | ")" | "[" | "]")) => false,
+            (Some(TokenKind::Punct("(" | "[")), _) => false,
+            _ => true,
+        };
+        if space {
+            result.push(' ');
+        }
+        result += &token.kind.to_string();
+
+        // 値の後にない - と ~ は単項演算子
+        let after_value = matches!(
+            prev,
+            Some(
+                TokenKind::Num(_)
+                    | TokenKind::Reg(_)
+                    | TokenKind::DataLabel(_)
+                    | TokenKind::InstLabel(_)
+                    | TokenKind::Punct(")" | "]")
+            )
+        );
+        unary = matches!(token.kind, TokenKind::Punct("-" | "~")) && !after_value;
+        prev = Some(&token.kind);
+    }
+    result
+}
+
+// 長いものから順に照合する
+#[rustfmt::skip]
+const PUNCTS: [&str; 19] = [
+    "===", "->", "<<", ">>",
+    "=", ",", "(", ")", "[", "]",
+    "-", "+", "*", "/", "%", "&", "|", "^", "~",
+];
+
+// ソース全体を行ごとのトークン列に分割する (空行・コメントのみの行は除く)
+// (file は SourceMap の番号)
+pub fn lex(source: &str, file: usize, errors: &mut Errors) -> Vec<Line> {
+    let mut lines = Vec::new();
+    for (idx, text) in source.lines().enumerate() {
+        match lex_line(file, idx + 1, text) {
+            Ok(tokens) if tokens.is_empty() => {}
+            Ok(tokens) => lines.push(Line {
+                tokens,
+                eol: Span::new(idx + 1, text.trim_end().len() + 1, 1).with_file(file),
+                expansion: None,
+            }),
+            Err(err) => errors.push(err),
+        }
+    }
+    lines
+}
+
+fn lex_line(file: usize, num: usize, text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() || rest.starts_with("//") {
+            break;
+        }
+
+        let col = text.len() - rest.len() + 1;
+        let (kind, len) = lex_token(rest)
+            .map_err(|(kind, len)| Error::new(kind, Span::new(num, col, len).with_file(file)))?;
+        tokens.push(Token {
+            kind,
+            span: Span::new(num, col, len).with_file(file),
+        });
+        rest = &rest[len..];
+    }
+    Ok(tokens)
+}
+
+// 先頭のトークンとその byte 長を返す
+fn lex_token(s: &str) -> Result<(TokenKind, usize), (ErrorKind, usize)> {
+    let first = s.chars().next().unwrap_or_default();
+
+    // 記号
+    if let Some(punct) = PUNCTS.iter().find(|&&p| s.starts_with(p)) {
+        return Ok((TokenKind::Punct(punct), punct.len()));
+    }
+
+    // 数値: 10進, 0x: 16進, 0b: 2進
+    if first.is_ascii_digit() {
+        let word = take_word(s);
+        return match parse_num(word) {
+            Some(num) => Ok((TokenKind::Num(num), word.len())),
+            None => Err((ErrorKind::InvalidValue(word.to_string()), word.len())),
+        };
+    }
+
+    // ラベル: $data, @inst
+    if first == '$' || first == '@' {
+        let name = take_word(&s[1..]);
+        if name.is_empty() {
+            return Err((ErrorKind::InvalidLabel(first.to_string()), 1));
+        }
+        let kind = match first {
+            '$' => TokenKind::DataLabel(name.to_string()),
+            _ => TokenKind::InstLabel(name.to_string()),
+        };
+        return Ok((kind, name.len() + 1));
+    }
+
+    // ディレクティブ (.macro, .endm, ...)
+    if first == '.' && s[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+        let word = take_word(s);
+        return Ok((TokenKind::Ident(word.to_string()), word.len()));
+    }
+
+    // 識別子, レジスタ (r0, r1, ..., r31)
+    if first.is_ascii_alphabetic() || first == '_' {
+        let word = take_word(s);
+        let kind = match word.strip_prefix("r") {
+            Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => match n.parse() {
+                Ok(reg) => TokenKind::Reg(reg),
+                Err(_) => return Err((ErrorKind::InvalidValue(word.to_string()), word.len())),
+            },
+            _ => TokenKind::Ident(word.to_string()),
+        };
+        return Ok((kind, word.len()));
+    }
+
+    // 文字列, 文字
+    if first == '"' || first == '\'' {
+        let (body, len) = take_quoted(s, first)?;
+        let kind = if first == '"' {
+            TokenKind::Str(body)
+        } else {
+            let mut chars = body.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => TokenKind::Char(c),
+                _ => return Err((ErrorKind::InvalidChar(s[..len].to_string()), len)),
+            }
+        };
+        return Ok((kind, len));
+    }
+
+    Err((ErrorKind::UnexpectedChar(first), first.len_utf8()))
+}
+
+fn take_word(s: &str) -> &str {
+    let len = s
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(s.len());
+    &s[..len]
+}
+
+fn parse_num(word: &str) -> Option<i64> {
+    if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = word.strip_prefix("0b").or_else(|| word.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        word.parse().ok()
+    }
+}
+
+// quote で囲まれた部分をエスケープを解釈して返す
+fn take_quoted(s: &str, quote: char) -> Result<(String, usize), (ErrorKind, usize)> {
+    let mut body = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            _ if c == quote => return Ok((body, idx + 1)),
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some((_, 'n')) => '\n',
+                    Some((_, 't')) => '\t',
+                    Some((_, 'r')) => '\r',
+                    Some((_, '0')) => '\0',
+                    Some((_, c @ ('\\' | '\'' | '"'))) => c,
+                    Some((idx, c)) => {
+                        return Err((ErrorKind::InvalidEscape(c), idx + c.len_utf8()))
+                    }
+                    None => break,
+                };
+                body.push(escaped);
+            }
+            _ => body.push(c),
+        }
+    }
+    Err((ErrorKind::UnterminatedQuote(quote), s.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex_ok(source: &str) -> Vec<Line> {
+        let mut errors = Errors::new(None);
+        let lines = lex(source, 2, &mut errors);
+        assert!(errors.is_empty());
+        lines
+    }
+
+    fn kinds(line: &Line) -> Vec<TokenKind> {
+        line.tokens.iter().map(|token| token.kind.clone()).collect()
+    }
+
+    fn lex_err(text: &str) -> (String, Span) {
+        let err = lex_line(0, 1, text).unwrap_err();
+        (err.kind.to_string(), err.span)
+    }
+
+    #[test]
+    fn tokens() {
+        let lines = lex_ok(
+            "    addi r1 = r31, -0x1F // comment\n$msg\n    string \"a\\\"\\n\", 'b', 0b101\n",
+        );
+        use TokenKind::*;
+        assert_eq!(
+            kinds(&lines[0]),
+            [
+                Ident("addi".into()),
+                Reg(1),
+                Punct("="),
+                Reg(31),
+                Punct(","),
+                Punct("-"),
+                Num(0x1F)
+            ]
+        );
+        assert_eq!(kinds(&lines[1]), [DataLabel("msg".into())]);
+        assert_eq!(
+            kinds(&lines[2]),
+            [
+                Ident("string".into()),
+                Str("a\"\n".into()),
+                Punct(","),
+                Char('b'),
+                Punct(","),
+                Num(5)
+            ]
+        );
+
+        let lines = lex_ok(".macro push reg\n@func_main.loop\n    beq r0, (r1, r2) -> @1f\n===\n    sw r2[4 << 1] = r3\n");
+        assert_eq!(
+            kinds(&lines[0]),
+            [
+                Ident(".macro".into()),
+                Ident("push".into()),
+                Ident("reg".into())
+            ]
+        );
+        assert_eq!(kinds(&lines[1]), [InstLabel("func_main.loop".into())]);
+        assert_eq!(
+            kinds(&lines[2])[7..],
+            [Punct(")"), Punct("->"), InstLabel("1f".into())]
+        );
+        assert_eq!(kinds(&lines[3]), [Punct("===")]);
+        assert_eq!(
+            kinds(&lines[4])[2..8],
+            [
+                Punct("["),
+                Num(4),
+                Punct("<<"),
+                Num(1),
+                Punct("]"),
+                Punct("=")
+            ]
+        );
+    }
+
+    #[test]
+    fn spans() {
+        // 空行とコメントだけの行は除くが, 行番号はソースのまま
+        let lines = lex_ok("\n// comment\n  lw r1 = r2[$buf]  \n");
+        assert_eq!(lines.len(), 1);
+        let spans = lines[0]
+            .tokens
+            .iter()
+            .map(|token| token.span)
+            .collect::<Vec<_>>();
+        let span = |col, len| Span::new(3, col, len).with_file(2);
+        assert_eq!(
+            spans,
+            [
+                span(3, 2),
+                span(6, 2),
+                span(9, 1),
+                span(11, 2),
+                span(13, 1),
+                span(14, 4),
+                span(18, 1)
+            ]
+        );
+        // 行末の位置は末尾の空白を除いた次の桁
+        assert_eq!(lines[0].eol, span(19, 1));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            lex_err("  add ?"),
+            (
+                ErrorKind::UnexpectedChar('?').to_string(),
+                Span::new(1, 7, 1)
+            )
+        );
+        assert_eq!(
+            lex_err("0x1G"),
+            (
+                ErrorKind::InvalidValue("0x1G".into()).to_string(),
+                Span::new(1, 1, 4)
+            )
+        );
+        assert_eq!(
+            lex_err("r99999"),
+            (
+                ErrorKind::InvalidValue("r99999".into()).to_string(),
+                Span::new(1, 1, 6)
+            )
+        );
+        assert_eq!(
+            lex_err("j @ "),
+            (
+                ErrorKind::InvalidLabel("@".into()).to_string(),
+                Span::new(1, 3, 1)
+            )
+        );
+        assert_eq!(
+            lex_err("char 'ab'"),
+            (
+                ErrorKind::InvalidChar("'ab'".into()).to_string(),
+                Span::new(1, 6, 4)
+            )
+        );
+        assert_eq!(
+            lex_err("string \"a\\q\""),
+            (
+                ErrorKind::InvalidEscape('q').to_string(),
+                Span::new(1, 8, 4)
+            )
+        );
+        assert_eq!(
+            lex_err("string \"abc"),
+            (
+                ErrorKind::UnterminatedQuote('"').to_string(),
+                Span::new(1, 8, 4)
+            )
+        );
+
+        // エラーの行は除いて続ける
+        let mut errors = Errors::new(None);
+        let lines = lex("nop ?\nnop\n", 0, &mut errors);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(errors.iter().count(), 1);
+    }
+
+    #[test]
+    fn render_tokens() {
+        for text in [
+            "sw r2[0] = r3",
+            "addi r1 = r0, -(1 + ~2) * 3",
+            "beq r0, (r1, r2) -> @1b",
+            "li r1 = 4 - -1",
+        ] {
+            let tokens = lex_line(0, 1, text).unwrap();
+            assert_eq!(render(&tokens), text);
+        }
+    }
+}
@@ -1,27 +1,307 @@
-mod dmem;
-mod imem;
+pub mod error;
+pub mod expr;
+pub mod source;
+pub mod object;
+
+pub mod dmem;
+pub mod imem;
+
+mod lex;
+mod syntax;
+mod include;
+mod constant;
+mod macros;
+mod linkage;
+mod section;
 
 mod check;
 mod convert;
+mod disasm;
+pub mod sim;
 mod resolve;
+mod link;
+mod listing;
+pub mod memory_map;
+pub mod symbol_map;
+pub mod format;
 
 use check::check;
-use convert::convert;
+use convert::{convert, inst_convert, pad};
+use error::{Error, ErrorKind, Errors};
+use include::lex_file;
+use object::{Object, Section};
 use resolve::resolve;
+use source::{FileLoader, SourceMap};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+pub use convert::{read_hex, to_hex};
+pub use object::read_object;
+pub use disasm::{decode, disassemble, disassemble_with};
+pub use symbol_map::SymbolMap;
+pub use memory_map::{read_memory_map, MemoryMap};
+
+pub struct Options {
+    pub chunk_size: usize,
+    // 報告するエラーの上限 (None: 無制限)
+    pub error_limit: Option<usize>,
+    // 各メモリの先頭アドレスと大きさ
+    // 大きさを指定するとイメージをその大きさまで fill で埋め, 超えたらエラーにする
+    pub memory_map: MemoryMap,
+    pub fill: u8,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            chunk_size: 1,
+            error_limit: None,
+            memory_map: MemoryMap::default(),
+            fill: 0,
+        }
+    }
+}
 
 pub fn assemble(program: &str, chunk_size: usize) -> anyhow::Result<(String, String)> {
+    let options = Options {
+        chunk_size,
+        ..Default::default()
+    };
+    assemble_with(program, &options)
+}
+
+// include は使えない (ソースの位置は SourceMap の 0 番のファイルとして報告する)
+pub fn assemble_with(program: &str, options: &Options) -> anyhow::Result<(String, String)> {
+    let mut sources = SourceMap::single("", program);
+    let (data, text) = assemble_source(&mut sources, &HashMap::new(), 0, options)?;
+    Ok(convert(data, text, options.chunk_size))
+}
+
+// path から読み込んで include を展開しながらアセンブルする
+// (エラーの表示に使うため, 読み込んだファイルは sources に追加される)
+pub fn assemble_file(
+    path: &Path,
+    loader: &dyn FileLoader,
+    sources: &mut SourceMap,
+    options: &Options,
+) -> anyhow::Result<(String, String)> {
+    let (data, text) = assemble_image(path, loader, sources, options)?;
+    Ok(convert(data, text, options.chunk_size))
+}
+
+// assemble_file と同じだが, hex にせず (data, inst) のメモリイメージのまま返す
+pub fn assemble_image(
+    path: &Path,
+    loader: &dyn FileLoader,
+    sources: &mut SourceMap,
+    options: &Options,
+) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    let file = load(path, loader, sources)?;
+    assemble_source(sources, loader, file, options)
+}
+
+// path から読み込んで, 他のオブジェクトとリンクするためのオブジェクトにする
+// (.extern で宣言したラベルはリンク時に他のオブジェクトの .global から探す)
+pub fn assemble_object(
+    path: &Path,
+    loader: &dyn FileLoader,
+    sources: &mut SourceMap,
+    options: &Options,
+) -> anyhow::Result<Object> {
+    let file = load(path, loader, sources)?;
+    compile(sources, loader, file, options)
+}
+
+// オブジェクトを順につなげて (data.hex, inst.hex) にする
+// (エラーの位置は load_sources で作った SourceMap で表示する)
+pub fn link(objects: &[Object], options: &Options) -> anyhow::Result<(String, String)> {
+    let (data, text) = link_image(objects, options)?;
+    Ok(convert(data, text, options.chunk_size))
+}
+
+// オブジェクトを順につなげて (data, inst) のメモリイメージにする
+// (イメージの先頭は memory_map の各メモリの先頭アドレス
+//  それぞれメモリの大きさまで埋め, さらに chunk_size の倍数まで fill で埋める)
+pub fn link_image(objects: &[Object], options: &Options) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    if options.chunk_size == 0 {
+        return Err(Errors::from(Error::global(ErrorKind::InvalidChunkSize(0))).into());
+    }
+
+    let map = &options.memory_map;
+    map.check()?;
+    let mut errors = Errors::new(options.error_limit);
+    let (data, text) = link::link(objects, map, &mut errors);
+    for (section, region) in [(Section::Data, map.dmem), (Section::Text, map.imem)] {
+        if let Some(size) = region.size {
+            link::check_capacity(objects, section, region.base, size, &mut errors);
+        }
+    }
+    errors.into_result()?;
+
+    let fill = |mut bytes: Vec<u8>, size: Option<usize>| {
+        if let Some(size) = size {
+            bytes.resize(bytes.len().max(size), options.fill);
+        }
+        pad(bytes, options.chunk_size, options.fill)
+    };
+    Ok((fill(data, map.dmem.size), fill(text, map.imem.size)))
+}
+
+// オブジェクトをリンクして, 各行のアドレス・エンコード・ソースを並べたリスティングを作る
+// (sources は load_sources で作ったもの, またはオブジェクトが 1 つならそれをアセンブルしたときのもの)
+pub fn listing(
+    objects: &[Object],
+    sources: &SourceMap,
+    map: &MemoryMap,
+) -> anyhow::Result<String> {
+    map.check()?;
+    let mut errors = Errors::new(None);
+    let (data, text) = link::link(objects, map, &mut errors);
+    errors.into_result()?;
+    Ok(listing::listing(objects, &data, &text, sources, map))
+}
+
+// 各オブジェクトのソースファイルを順に読み込む (読めないものは空とする)
+pub fn load_sources(objects: &[Object], loader: &dyn FileLoader) -> SourceMap {
+    let mut sources = SourceMap::new();
+    for path in objects.iter().flat_map(|object| &object.files) {
+        if sources.load(loader, path).is_err() {
+            sources.add(path, "");
+        }
+    }
+    sources
+}
+
+fn load(path: &Path, loader: &dyn FileLoader, sources: &mut SourceMap) -> anyhow::Result<usize> {
+    let file = sources.load(loader, path).map_err(|err| {
+        Errors::from(Error::global(ErrorKind::CannotRead {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        }))
+    })?;
+    Ok(file)
+}
+
+fn assemble_source(
+    sources: &mut SourceMap,
+    loader: &dyn FileLoader,
+    file: usize,
+    options: &Options,
+) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    if options.chunk_size == 0 {
+        return Err(Errors::from(Error::global(ErrorKind::InvalidChunkSize(0))).into());
+    }
+
+    let object = compile(sources, loader, file, options)?;
+    link_image(&[object], options)
+}
+
+// 1 つのソースをオブジェクトにする
+fn compile(
+    sources: &mut SourceMap,
+    loader: &dyn FileLoader,
+    file: usize,
+    options: &Options,
+) -> anyhow::Result<Object> {
+    // 字句解析
+    let mut errors = Errors::new(options.error_limit);
+    let lines = lex_file(sources, loader, file, &mut errors);
+    let lines = constant::substitute(&lines, sources, &mut errors);
+    let (lines, mut linkage) = linkage::collect(lines, &mut errors);
+    // メモリマップの領域 ($名前) はリンク時に決まる
+    for (name, _) in &options.memory_map.regions {
+        linkage.declare_extern('$', name);
+    }
+
     // 分割
-    let lines = program.lines().collect::<Vec<_>>();
-    let sep_pos = lines.iter().position(|&line| line == "===").unwrap();
+    let Some((data_lines, inst_lines)) = section::split(&lines, &mut errors) else {
+        errors.push(Error::global(ErrorKind::SeparatorNotFound));
+        return Err(errors.into());
+    };
 
     // 構文解析
-    let datas = dmem::parse(&lines[..sep_pos])?;
-    let insts = imem::parse(&lines[(sep_pos + 1)..])?;
+    let (datas, data_end) = dmem::parse(&data_lines, &mut errors);
+    let inst_lines = macros::expand(&inst_lines, &mut errors);
+    let (mut insts, mut inst_end) = imem::parse(&inst_lines, &mut errors);
+    resolve::scoped_labels(&mut insts, &mut inst_end);
+    resolve::numeric_labels(&mut insts, &mut inst_end);
 
     // 意味解析
-    check(&datas, &insts)?;
+    check(&datas, &data_end, &insts, &inst_end, &linkage, &mut errors);
+    if !errors.is_empty() {
+        return Err(errors.into());
+    }
 
     // コード生成
-    let insts = resolve(&datas, insts)?;
-    convert(datas, insts, chunk_size)
+    let items = resolve::items(&datas, &insts);
+    let (data, insts, symbols, relocs) = resolve(&datas, &data_end, insts, &inst_end, &linkage, &mut errors);
+    errors.into_result()?;
+    Ok(Object {
+        files: sources.iter().map(|file| file.path.clone()).collect(),
+        data,
+        text: inst_convert(insts)?,
+        symbols,
+        relocs,
+        items,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fuzz/fuzz_targets/assemble.rs で見つけるパニックの種類ごとの入力
+    // (エラーとして報告し, その表示もパニックしない)
+    #[test]
+    fn crash_regressions() {
+        let deep = format!(
+            "===\n    addi r1 = r0, {}1{}\n",
+            "(".repeat(10000),
+            ")".repeat(10000)
+        );
+        #[rustfmt::skip]
+        let cases = [
+            // 区切りがない, chunk_size が 0
+            ("", 6, "Section \".text\" (or separator \"===\") is not found"),
+            ("$a\n", 0, "Invalid chunk size: 0"),
+            // 値のないデータ行のラベル
+            ("$a\n    byte1\n===\n    nop\n", 6, "Unexpected token(expect: expression): end of line"),
+            // 見つからないラベル, 分岐先のデータラベル
+            ("===\n    j @nowhere\n", 6, "label @nowhere is not found"),
+            ("===\n@1\n    j @2f\n", 6, "label @2f is not found"),
+            ("$a\n    byte1 1\n===\n    beq r0, (r1, r2) -> $a\n", 6, "Datalabel is not permitted in branch instruction: $a"),
+            // 数値と式の評価
+            ("===\n    addi r1 = r0, 99999999999999999999\n", 6, "Invalid value: 99999999999999999999"),
+            (&deep, 6, "Expression is nested too deeply (limit: 256)"),
+            ("===\n    addi r1 = r0, 1 % 0\n", 6, "Division by zero in expression: (1 % 0)"),
+            ("===\n    addi r1 = r0, 1 << 64\n", 6, "Arithmetic overflow in expression: (1 << 64)"),
+            ("===\n    addi r1 = r0, 0x7FFFFFFFFFFFFFFF * 2\n", 6, "Arithmetic overflow in expression: (9223372036854775807 * 2)"),
+            // 複数 byte の文字の位置, 閉じていない文字列
+            ("===\n    é ü\n", 6, "Unexpected character: 'é'"),
+            ("$s\n    string \"\\", 6, "Unterminated literal (missing \")"),
+            // 再帰するマクロ
+            ("===\n.macro m\n    m\n.endm\n    m\n", 6, "Recursive macro invocation: m"),
+        ];
+        for (program, chunk_size, expected) in cases {
+            let err = assemble(program, chunk_size).unwrap_err();
+            let errors = err.downcast::<Errors>().unwrap();
+            // 表示もパニックしない
+            errors.render(&SourceMap::single("fuzz.asm", program));
+            let first = errors.iter().next().unwrap().kind.to_string();
+            assert_eq!(first, expected, "{:?}", program);
+        }
+    }
+
+    #[test]
+    fn any_chunk_size() {
+        // fuzz の入力の先頭 1 byte が取りうる chunk_size
+        let program = "$a\n    byte1 1, 2, 3\n===\n    li r1 = 0x12345678\n";
+        for chunk_size in 1..=255 {
+            let (data, inst) = assemble(program, chunk_size).unwrap();
+            let bytes = read_hex(&data, chunk_size).unwrap();
+            assert_eq!(bytes[..3], [1, 2, 3]);
+            assert!(read_hex(&inst, chunk_size).is_ok());
+        }
+    }
 }
@@ -0,0 +1,501 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use crate::error::{Error, ErrorKind, Errors, Span};
+use crate::memory_map::MemoryMap;
+use crate::object::{Object, Reloc, RelocKind, Section};
+use crate::resolve::check_range;
+
+// ラベル名 -> アドレス (byte)
+#[derive(Clone, Default)]
+struct Labels {
+    data: HashMap<String, usize>,
+    inst: HashMap<String, usize>,
+}
+
+// オブジェクトのセクションを順につなげてアドレスを決め, 再配置情報の式を計算して埋める
+// 式のラベルはそのオブジェクトのもの, 他のオブジェクトの .global, メモリマップの領域の順に探す
+// (アドレスは各メモリの先頭アドレスから. エラーの Span::file は, 各オブジェクトの files を順につなげたときの番号にする)
+pub fn link(objects: &[Object], map: &MemoryMap, errors: &mut Errors) -> (Vec<u8>, Vec<u8>) {
+    let mut data = Vec::new();
+    let mut text = Vec::new();
+    let files = objects
+        .iter()
+        .flat_map(|object| &object.files)
+        .collect::<Vec<_>>();
+
+    // 各オブジェクトの (data, text の先頭のイメージ上の位置, files の先頭の番号)
+    let mut bases = Vec::new();
+    let mut file_base = 0;
+    for object in objects {
+        bases.push((data.len(), text.len(), file_base));
+        data.extend(&object.data);
+        text.extend(&object.text);
+        file_base += object.files.len();
+    }
+
+    // ラベルのアドレスは i64 で計算するので, セクションの終わりまでがその範囲に収まらなければならない
+    for (name, region, len) in [
+        ("dmem", map.dmem, data.len()),
+        ("imem", map.imem, text.len()),
+    ] {
+        let end = region.base.checked_add(len);
+        if end.is_none_or(|end| i64::try_from(end).is_err()) {
+            let reason = format!("region `{}` does not fit in the address space", name);
+            errors.push(Error::global(ErrorKind::InvalidMemoryMap(reason)));
+            return (data, text);
+        }
+    }
+
+    // .global のラベルとそれを定義した位置
+    let mut globals = Labels::default();
+    for (name, region) in &map.regions {
+        globals.insert(Section::Data, name, region.base);
+    }
+    let mut defined = HashMap::new();
+    for (object, &(data_base, text_base, file_base)) in objects.iter().zip(&bases) {
+        for symbol in object.symbols.iter().filter(|symbol| symbol.global) {
+            let span = remap(symbol.span, file_base);
+            match defined.entry((symbol.section, symbol.name.clone())) {
+                Entry::Occupied(entry) => {
+                    let prefix = prefix(symbol.section);
+                    let first: &Span = entry.get();
+                    let note = match files.get(first.file) {
+                        Some(path) => format!(
+                            "first defined at {}:{}:{}",
+                            path.display(),
+                            first.line,
+                            first.col
+                        ),
+                        None => "first defined in another object".to_string(),
+                    };
+                    errors.push(
+                        Error::new(
+                            ErrorKind::GlobalRedefined(format!("{}{}", prefix, symbol.name)),
+                            span,
+                        )
+                        .with_note(Some(note)),
+                    );
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(span);
+                    let base = match symbol.section {
+                        Section::Data => map.dmem.base + data_base,
+                        Section::Text => map.imem.base + text_base,
+                    };
+                    globals.insert(symbol.section, &symbol.name, base + symbol.offset);
+                }
+            }
+        }
+    }
+
+    for (object, &(data_base, text_base, file_base)) in objects.iter().zip(&bases) {
+        // このオブジェクトのラベルは .global でなくても使え, 他のものより優先する
+        let mut labels = globals.clone();
+        for symbol in &object.symbols {
+            let base = match symbol.section {
+                Section::Data => map.dmem.base + data_base,
+                Section::Text => map.imem.base + text_base,
+            };
+            labels.insert(symbol.section, &symbol.name, base + symbol.offset);
+        }
+
+        for reloc in &object.relocs {
+            let span = remap(reloc.span, file_base);
+            let (bytes, section_base, base) = match reloc.section {
+                Section::Data => (&mut data, data_base, map.dmem.base),
+                Section::Text => (&mut text, text_base, map.imem.base),
+            };
+            let pos = section_base.saturating_add(reloc.offset);
+            match labels.calc(reloc, pos, base, bytes.len(), span) {
+                Ok(value) => reloc.kind.patch(&mut bytes[pos..], value),
+                Err(err) => errors.push(err.with_note(reloc.note.clone())),
+            }
+        }
+    }
+
+    (data, text)
+}
+
+// オブジェクト内のファイルの番号を, 全体でつなげたときの番号にする
+fn remap(span: Span, file_base: usize) -> Span {
+    Span {
+        file: file_base + span.file,
+        ..span
+    }
+}
+
+fn prefix(section: Section) -> char {
+    match section {
+        Section::Data => '$',
+        Section::Text => '@',
+    }
+}
+
+impl Labels {
+    fn insert(&mut self, section: Section, name: &str, addr: usize) {
+        let labels = match section {
+            Section::Data => &mut self.data,
+            Section::Text => &mut self.inst,
+        };
+        labels.insert(name.to_string(), addr);
+    }
+
+    // pos は埋めるフィールドを含むデータ・命令のイメージ上の位置, base はメモリの先頭アドレス
+    fn calc(
+        &self,
+        reloc: &Reloc,
+        pos: usize,
+        base: usize,
+        len: usize,
+        span: Span,
+    ) -> Result<i64, Error> {
+        let size = reloc.kind.size();
+        if pos.checked_add(size).is_none_or(|end| end > len) {
+            let reason = format!(
+                "relocation at offset {} is out of the section",
+                reloc.offset
+            );
+            return Err(Error::new(ErrorKind::InvalidObject(reason), span));
+        }
+        // セクションの終わりまでが i64 に収まることは link で確かめてある
+        let addr = base + pos;
+
+        let value = reloc
+            .expr
+            .eval(&self.data, &self.inst)
+            .map_err(|kind| Error::new(kind, span))?;
+        // 分岐先が命令のアドレスなら pc からの相対値にする
+        let value = if reloc.kind == RelocKind::Branch && reloc.expr.is_inst_addr() {
+            value - addr as i64
+        } else {
+            value
+        };
+        check_range(value, reloc.kind.range(), span)
+    }
+}
+
+// セクションが capacity (byte) を超えたら, はみ出したラベルを挙げてエラーにする
+// (最後まで収まらないラベルの位置で報告する. ラベルのアドレスは先頭アドレス base から)
+pub fn check_capacity(
+    objects: &[Object],
+    section: Section,
+    memory_base: usize,
+    capacity: usize,
+    errors: &mut Errors,
+) {
+    let len = objects
+        .iter()
+        .map(|object| match section {
+            Section::Data => object.data.len(),
+            Section::Text => object.text.len(),
+        })
+        .sum::<usize>();
+    if len <= capacity {
+        return;
+    }
+
+    // (アドレス, 名前, 位置) をアドレス順に
+    let mut labels = Vec::new();
+    let (mut base, mut file_base) = (0, 0);
+    for object in objects {
+        for symbol in object
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.section == section)
+        {
+            let span = remap(symbol.span, file_base);
+            labels.push((base + symbol.offset, &symbol.name, span));
+        }
+        base += match section {
+            Section::Data => object.data.len(),
+            Section::Text => object.text.len(),
+        };
+        file_base += object.files.len();
+    }
+    labels.sort_by_key(|(addr, _, _)| *addr);
+
+    // capacity より後ろのラベルと, 次のラベルまでが capacity をまたぐラベル
+    let mut first = labels.partition_point(|(addr, _, _)| *addr < capacity);
+    let next = labels.get(first).map_or(len, |(addr, _, _)| *addr);
+    if first > 0 && next > capacity {
+        first -= 1;
+    }
+    let overflowing = &labels[first..];
+
+    let kind = ErrorKind::MemoryOverflow {
+        memory: match section {
+            Section::Data => "data".to_string(),
+            Section::Text => "instruction".to_string(),
+        },
+        used: len,
+        capacity,
+    };
+    let error = match overflowing.first() {
+        Some((_, _, span)) => {
+            let names = overflowing
+                .iter()
+                .map(|(addr, name, _)| {
+                    let addr = memory_base + addr;
+                    format!("{}{} ({:#X})", prefix(section), name, addr)
+                })
+                .collect::<Vec<_>>();
+            let note = format!("labels not fitting in the memory: {}", names.join(", "));
+            Error::new(kind, *span).with_note(Some(note))
+        }
+        None => Error::global(kind),
+    };
+    errors.push(error);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::memory_map::Region;
+    use crate::object::Reloc;
+    use crate::source::SourceMap;
+    use crate::{assemble_image, assemble_object, link_image, read_hex, to_hex, Options};
+
+    fn compile(path: &str, source: &str) -> Object {
+        let files = HashMap::from([(PathBuf::from(path), source.to_string())]);
+        let mut sources = SourceMap::new();
+        assemble_object(Path::new(path), &files, &mut sources, &Options::default()).unwrap()
+    }
+
+    #[test]
+    fn cross_references() {
+        let main = "
+.data
+$count
+    byte4 $msg + 2
+.text
+@main
+    la r10 = $msg
+    call @print
+    j @main
+";
+        let lib = "
+.data
+$msg
+    string \"Hi\"
+.text
+@print
+    lw r1 = r0[$count]
+    beq r0, (r0, r0) -> @main
+";
+        let a = compile(
+            "main.asm",
+            &format!(".global $count, @main\n.extern $msg, @print\n{}", main),
+        );
+        let b = compile(
+            "lib.asm",
+            &format!(".global $msg, @print\n.extern $count, @main\n{}", lib),
+        );
+        let linked = link_image(&[a, b], &Options::default()).unwrap();
+
+        // 1 つのソースにまとめてアセンブルしたものと同じになる
+        let files = HashMap::from([(PathBuf::from("all.asm"), format!("{}{}", main, lib))]);
+        let mut sources = SourceMap::new();
+        let options = Options::default();
+        let image = assemble_image(Path::new("all.asm"), &files, &mut sources, &options).unwrap();
+        assert_eq!(linked, image);
+    }
+
+    #[test]
+    fn reloc_out_of_section() {
+        let mut object = compile("main.asm", ".text\n@main\n    j @main\n");
+        object.relocs.push(Reloc {
+            offset: usize::MAX,
+            ..object.relocs[0].clone()
+        });
+        let mut errors = Errors::new(None);
+        link(&[object], &MemoryMap::default(), &mut errors);
+        let kinds = errors.iter().map(|err| &err.kind).collect::<Vec<_>>();
+        assert!(
+            matches!(kinds[..], [ErrorKind::InvalidObject(_)]),
+            "{:?}",
+            kinds
+        );
+    }
+
+    fn link_errors(objects: &[Object]) -> Vec<(String, Span)> {
+        let mut errors = Errors::new(None);
+        link(objects, &MemoryMap::default(), &mut errors);
+        errors
+            .iter()
+            .map(|err| (err.kind.to_string(), err.span))
+            .collect()
+    }
+
+    #[test]
+    fn undefined_extern() {
+        let main = compile("main.asm", ".extern @print\n.text\n    call @print\n");
+        // print は lib.asm の中だけのラベル
+        let lib = compile("lib.asm", ".text\n@print\n    ret\n");
+        assert_eq!(
+            link_errors(&[main, lib]),
+            [("label @print is not found".to_string(), Span::new(3, 10, 6))]
+        );
+    }
+
+    #[test]
+    fn global_redefined() {
+        let a = compile("a.asm", ".global @init\n.text\n@init\n    ret\n");
+        let b = compile("b.asm", ".global @init\n.text\n    nop\n@init\n    ret\n");
+        let mut errors = Errors::new(None);
+        link(&[a, b], &MemoryMap::default(), &mut errors);
+        let errors = errors.iter().collect::<Vec<_>>();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].kind.to_string(),
+            "global label @init is defined multiple times"
+        );
+        // 2 つ目のオブジェクトのファイルの番号 (files をつなげたときのもの)
+        assert_eq!(errors[0].span, Span::new(4, 1, 5).with_file(1));
+        assert_eq!(
+            errors[0].note.as_deref(),
+            Some("first defined at a.asm:3:1")
+        );
+    }
+
+    #[test]
+    fn global_not_defined() {
+        let files = HashMap::from([(
+            PathBuf::from("main.asm"),
+            ".global @main, $buf\n.text\n@main\n    ret\n".to_string(),
+        )]);
+        let mut sources = SourceMap::new();
+        let options = Options::default();
+        let err =
+            assemble_object(Path::new("main.asm"), &files, &mut sources, &options).unwrap_err();
+        let errors = err.downcast::<Errors>().unwrap();
+        let kinds = errors
+            .iter()
+            .map(|err| err.kind.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, ["global label $buf is not defined"]);
+    }
+
+    #[test]
+    fn local_labels_in_each_object() {
+        let loop_a =
+            ".text\n    li r1 = 3\n@loop\n    subi r1 = r1, 1\n    bne r0, (r1, r0) -> @loop\n";
+        let loop_b = ".text\n    nop\n@loop\n    j @loop\n";
+        let linked = link_image(
+            &[compile("a.asm", loop_a), compile("b.asm", loop_b)],
+            &Options::default(),
+        )
+        .unwrap();
+
+        let all = format!("{}{}", loop_a, loop_b.replace("@loop", "@loop_b"));
+        let files = HashMap::from([(PathBuf::from("all.asm"), all)]);
+        let mut sources = SourceMap::new();
+        let options = Options::default();
+        let image = assemble_image(Path::new("all.asm"), &files, &mut sources, &options).unwrap();
+        assert_eq!(linked, image);
+    }
+
+    #[test]
+    fn binary_matches_hex() {
+        // --format bin はイメージの byte をそのまま書くので, hex を読んだ byte と同じになる
+        let object = compile("main.asm", "$a\n    byte1 1, 2, 3\n.text\n    nop\n");
+        let mut options = Options {
+            fill: 0xAA,
+            ..Options::default()
+        };
+        options.memory_map.dmem = Region {
+            base: 0,
+            size: Some(8),
+        };
+        options.memory_map.imem = Region {
+            base: 0,
+            size: Some(16),
+        };
+        for chunk_size in [1, 4, 6] {
+            options.chunk_size = chunk_size;
+            let (data, text) = link_image(std::slice::from_ref(&object), &options).unwrap();
+            let (data_hex, text_hex) =
+                crate::link(std::slice::from_ref(&object), &options).unwrap();
+            assert_eq!(data_hex, to_hex(data.clone(), chunk_size));
+            assert_eq!(text_hex, to_hex(text.clone(), chunk_size));
+            assert_eq!(read_hex(&data_hex, chunk_size).unwrap(), data);
+            assert_eq!(read_hex(&text_hex, chunk_size).unwrap(), text);
+
+            // 大きさまで fill で埋め, さらに語の境界まで埋める
+            assert_eq!(data.len(), 8usize.next_multiple_of(chunk_size));
+            assert_eq!(data[..3], [1, 2, 3]);
+            assert!(data[3..].iter().all(|&byte| byte == 0xAA));
+            assert_eq!(text.len(), 16usize.next_multiple_of(chunk_size));
+            assert!(text[6..].iter().all(|&byte| byte == 0xAA));
+        }
+    }
+
+    // (メッセージ, 位置, 注記)
+    fn capacity_errors(
+        objects: &[Object],
+        section: Section,
+        capacity: usize,
+    ) -> Vec<(String, Span, Option<String>)> {
+        let mut errors = Errors::new(None);
+        check_capacity(objects, section, 0x100, capacity, &mut errors);
+        errors
+            .iter()
+            .map(|err| (err.kind.to_string(), err.span, err.note.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn memory_overflow() {
+        let a = compile(
+            "a.asm",
+            ".text\n@main\n    nop\n    nop\n@main.loop\n    j @main.loop\n",
+        );
+        let b = compile(
+            "b.asm",
+            "$msg\n    byte1 1, 2\n.text\n@print\n    nop\n@print.end\n    ret\n",
+        );
+        let objects = [a, b];
+        assert!(capacity_errors(&objects, Section::Text, 30).is_empty());
+
+        // 途中までしか収まらないラベルから, 後ろのラベルをすべて挙げる (位置は最初のもの)
+        let errors = capacity_errors(&objects, Section::Text, 20);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].0,
+            "instruction memory overflows: 30 bytes are used, but the capacity is 20 bytes"
+        );
+        assert_eq!(errors[0].1, Span::new(4, 1, 6).with_file(1));
+        assert_eq!(
+            errors[0].2.as_deref(),
+            Some("labels not fitting in the memory: @print (0x112), @print.end (0x118)")
+        );
+
+        // ちょうど境界から始まるラベルは, その前のラベルを含めない
+        let errors = capacity_errors(&objects, Section::Text, 24);
+        assert_eq!(
+            errors[0].2.as_deref(),
+            Some("labels not fitting in the memory: @print.end (0x118)")
+        );
+
+        // 境界をまたぐデータのラベル
+        let errors = capacity_errors(&objects, Section::Data, 1);
+        assert_eq!(
+            errors[0].0,
+            "data memory overflows: 2 bytes are used, but the capacity is 1 bytes"
+        );
+        assert_eq!(errors[0].1, Span::new(1, 1, 4).with_file(1));
+        assert_eq!(
+            errors[0].2.as_deref(),
+            Some("labels not fitting in the memory: $msg (0x100)")
+        );
+
+        // ラベルがなければ位置を持たない
+        let c = compile("c.asm", "    byte1 1, 2\n.text\n    nop\n");
+        let errors = capacity_errors(&[c], Section::Data, 1);
+        assert_eq!(errors[0].1, Span::default());
+        assert_eq!(errors[0].2, None);
+    }
+}
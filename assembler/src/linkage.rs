@@ -0,0 +1,99 @@
+use crate::error::{Error, Errors, Span};
+use crate::lex::{Line, Token, TokenKind};
+use crate::syntax::Parser;
+
+// .global / .extern で宣言したラベル
+// (ラベルはファイル内でだけ使えるのが既定で, .global にしたものだけ他のオブジェクトから使える)
+//
+// .global @print, $hello
+// .extern @main
+#[derive(Debug, Default)]
+pub struct Linkage {
+    pub globals: Vec<Decl>,
+    pub externs: Vec<Decl>,
+}
+
+#[derive(Debug)]
+pub struct Decl {
+    // '$': データ, '@': 命令
+    pub prefix: char,
+    pub name: String,
+    pub span: Span,
+}
+
+impl Linkage {
+    pub fn is_global(&self, prefix: char, name: &str) -> bool {
+        contains(&self.globals, prefix, name)
+    }
+
+    pub fn is_extern(&self, prefix: char, name: &str) -> bool {
+        contains(&self.externs, prefix, name)
+    }
+
+    pub fn declare_extern(&mut self, prefix: char, name: &str) {
+        self.externs.push(Decl {
+            prefix,
+            name: name.to_string(),
+            span: Span::default(),
+        });
+    }
+}
+
+fn contains(decls: &[Decl], prefix: char, name: &str) -> bool {
+    decls
+        .iter()
+        .any(|decl| decl.prefix == prefix && decl.name == name)
+}
+
+// .global / .extern の行を取り除いて宣言を集める (セクションに関わらずどこに書いてもよい)
+pub fn collect(lines: Vec<Line>, errors: &mut Errors) -> (Vec<Line>, Linkage) {
+    let mut linkage = Linkage::default();
+    let mut result = Vec::new();
+    for line in lines {
+        let decls = match &line.tokens[0].kind {
+            TokenKind::Ident(ident) if ident == ".global" => &mut linkage.globals,
+            TokenKind::Ident(ident) if ident == ".extern" => &mut linkage.externs,
+            _ => {
+                result.push(line);
+                continue;
+            }
+        };
+        match parse_decls(&line) {
+            Ok(line_decls) => decls.extend(line_decls),
+            Err(err) => errors.push(err),
+        }
+    }
+    (result, linkage)
+}
+
+// .global label, label, ...
+fn parse_decls(line: &Line) -> Result<Vec<Decl>, Error> {
+    let mut parser = Parser::new(line);
+    parser.expect_ident()?;
+
+    let mut decls = Vec::new();
+    loop {
+        let (prefix, name) = match parser.peek() {
+            Some(Token {
+                kind: TokenKind::DataLabel(name),
+                ..
+            }) => ('$', name.clone()),
+            Some(Token {
+                kind: TokenKind::InstLabel(name),
+                ..
+            }) => ('@', name.clone()),
+            _ => return Err(parser.unexpected("label")),
+        };
+        parser.bump();
+        decls.push(Decl {
+            prefix,
+            name,
+            span: parser.prev_span(),
+        });
+        if !parser.eat_punct(",") {
+            break;
+        }
+    }
+    parser.expect_end()?;
+    Ok(decls)
+}
@@ -0,0 +1,241 @@
+use crate::error::Span;
+use crate::memory_map::MemoryMap;
+use crate::object::{Item, Object, Section, Symbol};
+use crate::source::SourceMap;
+
+// データは 1 行に 8 byte, 命令は 1 行に 1 命令ずつ
+const DATA_BYTES_PER_LINE: usize = 8;
+
+// リンクしたセクション (data, text) の各行に, アドレス・エンコード・ソースの行を並べる
+// ラベルは外側のラベルを付けた名前で, それが指すアドレスの行に書く
+// (アドレスはメモリマップの各メモリの先頭アドレスから)
+//
+// .text
+// 00000000                    main.asm:5  func_main:
+// 00000000  000001000222      main.asm:7      addi r2 = r0, 0x100
+pub fn listing(
+    objects: &[Object],
+    data: &[u8],
+    text: &[u8],
+    sources: &SourceMap,
+    map: &MemoryMap,
+) -> String {
+    // ソースの位置の列の幅
+    let mut width = 0;
+    let mut file_base = 0;
+    for object in objects {
+        let spans = object.items.iter().map(|item| item.span);
+        let spans = spans.chain(object.symbols.iter().map(|symbol| symbol.span));
+        for span in spans {
+            width = width.max(location(sources, file_base, span).len());
+        }
+        file_base += object.files.len();
+    }
+
+    let mut result = String::new();
+    for (section, bytes) in [(Section::Data, data), (Section::Text, text)] {
+        result += match section {
+            Section::Data => ".data\n",
+            Section::Text => ".text\n",
+        };
+
+        let memory_base = match section {
+            Section::Data => map.dmem.base,
+            Section::Text => map.imem.base,
+        };
+        let mut base = 0;
+        let mut file_base = 0;
+        for object in objects {
+            let rows = Rows {
+                section,
+                bytes,
+                memory_base,
+                base,
+                file_base,
+                sources,
+                width,
+            };
+            result += &rows.render(object);
+
+            base += match section {
+                Section::Data => object.data.len(),
+                Section::Text => object.text.len(),
+            };
+            file_base += object.files.len();
+        }
+    }
+    result
+}
+
+// 1 つのオブジェクトの 1 つのセクション分
+struct Rows<'a> {
+    section: Section,
+    bytes: &'a [u8],
+    // メモリの先頭アドレス
+    memory_base: usize,
+    // このオブジェクトのセクションの先頭の bytes 上の位置
+    base: usize,
+    // このオブジェクトの files の先頭の番号
+    file_base: usize,
+    sources: &'a SourceMap,
+    width: usize,
+}
+
+impl Rows<'_> {
+    fn render(&self, object: &Object) -> String {
+        let mut symbols = object
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.section == self.section)
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|symbol| symbol.offset);
+        let mut symbols = symbols.into_iter().peekable();
+
+        let items = object
+            .items
+            .iter()
+            .filter(|item| item.section == self.section)
+            .collect::<Vec<_>>();
+
+        let mut result = String::new();
+        // 同じ行から出てきた (続いている) データ・命令はまとめる
+        // マクロの展開は, 呼び出しの行の後に展開した行を並べる
+        let mut call = None;
+        for group in items.chunk_by(|a, b| {
+            same_line(a.span, b.span)
+                && a.offset + a.len == b.offset
+                && a.expansion.as_ref().map(|(call, _)| call)
+                    == b.expansion.as_ref().map(|(call, _)| call)
+        }) {
+            let offset = group[0].offset;
+            let len = group.iter().map(|item| item.len).sum::<usize>();
+            while let Some(symbol) = symbols.next_if(|symbol| symbol.offset <= offset) {
+                result += &self.label(symbol);
+            }
+            let expansion = group[0].expansion.as_ref();
+            if let Some((span, _)) = expansion.filter(|(span, _)| call != Some(*span)) {
+                let text = self.source_line(*span).unwrap_or("").trim();
+                result += &self.row(offset, &[], *span, &format!("    {}", text));
+            }
+            call = expansion.map(|(span, _)| *span);
+            result += &self.item(group[0], offset, len);
+        }
+        for symbol in symbols {
+            result += &self.label(symbol);
+        }
+        result
+    }
+
+    fn label(&self, symbol: &Symbol) -> String {
+        let text = format!("{}:", symbol.name);
+        self.row(symbol.offset, &[], symbol.span, &text)
+    }
+
+    fn item(&self, item: &Item, offset: usize, len: usize) -> String {
+        let start = self.base + offset;
+        let bytes = self.bytes.get(start..(start + len)).unwrap_or(&[]);
+        let text = match &item.expansion {
+            Some((_, text)) => format!("    {}", text),
+            None => self.source_line(item.span).unwrap_or("").trim().to_string(),
+        };
+        let per_line = match self.section {
+            Section::Data => DATA_BYTES_PER_LINE,
+            Section::Text => 6,
+        };
+
+        let mut result = String::new();
+        for (idx, chunk) in bytes.chunks(per_line).enumerate() {
+            // 2 行目以降はアドレスとエンコードだけ
+            if idx == 0 {
+                result += &self.row(offset, chunk, item.span, &format!("    {}", text));
+            } else {
+                result += &self.row(offset + idx * per_line, chunk, Span::default(), "");
+            }
+        }
+        result
+    }
+
+    fn row(&self, offset: usize, bytes: &[u8], span: Span, text: &str) -> String {
+        let encoding = match self.section {
+            // 命令は 48bit の命令語として書く
+            Section::Text if bytes.len() == 6 => bytes
+                .iter()
+                .rev()
+                .map(|byte| format!("{:02X}", byte))
+                .collect(),
+            _ => bytes
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<String>(),
+        };
+        let row = format!(
+            "{:08X}  {:<16}  {:<width$}  {}",
+            self.memory_base + self.base + offset,
+            encoding,
+            location(self.sources, self.file_base, span),
+            text,
+            width = self.width
+        );
+        format!("{}\n", row.trim_end())
+    }
+
+    fn source_line(&self, span: Span) -> Option<&str> {
+        let file = self.sources.get(self.file_base + span.file)?;
+        file.source.lines().nth(span.line.checked_sub(1)?)
+    }
+}
+
+// path:line
+fn location(sources: &SourceMap, file_base: usize, span: Span) -> String {
+    match sources.get(file_base + span.file) {
+        Some(file) if span.line != 0 => format!("{}:{}", file.path.display(), span.line),
+        _ => String::new(),
+    }
+}
+
+fn same_line(a: Span, b: Span) -> bool {
+    a.file == b.file && a.line == b.line
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use crate::memory_map::MemoryMap;
+    use crate::source::SourceMap;
+    use crate::{assemble_object, Options};
+
+    #[test]
+    fn macro_expansion() {
+        let source = ".text
+.macro push reg
+    subi r2 = r2, 4
+    sw r2[0] = reg
+.endm
+@main
+    push r3
+    nop
+";
+        let files = HashMap::from([(PathBuf::from("main.asm"), source.to_string())]);
+        let mut sources = SourceMap::new();
+        let options = Options::default();
+        let object =
+            assemble_object(Path::new("main.asm"), &files, &mut sources, &options).unwrap();
+        let listing = crate::listing(&[object], &sources, &MemoryMap::default()).unwrap();
+        let lines = listing
+            .lines()
+            .skip_while(|line| *line != ".text")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines[1..],
+            [
+                "00000000                    main.asm:6  main:",
+                "00000000                    main.asm:7      push r3",
+                "00000000  000000044242      main.asm:3          subi r2 = r2, 4",
+                "00000006  000000004305      main.asm:4          sw r2[0] = r3",
+                "0000000C  000000000021      main.asm:8      nop",
+            ]
+        );
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+use crate::error::{Error, ErrorKind, Errors, Span};
+use crate::lex::{render, Line, Token, TokenKind};
+use crate::resolve::is_numeric;
+use crate::syntax::Parser;
+
+// 展開で作る行の数の上限 (入れ子の呼び出しで指数的に増えるのを防ぐ)
+pub const MAX_EXPANDED_LINES: usize = 1 << 20;
+
+// .macro name param, ... の定義
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Line>,
+}
+
+// 展開中の状態
+struct Expander<'a> {
+    macros: &'a HashMap<String, Macro>,
+    // 展開した回数 (マクロ内のラベルを一意にするのに使う)
+    count: usize,
+    // 展開中のマクロ (再帰の検出用)
+    stack: Vec<String>,
+    // 展開で作った行の数と, その上限
+    lines: usize,
+    limit: usize,
+}
+
+// 命令部のマクロ定義を取り除き, 呼び出しを展開する
+//
+// .macro push reg
+//     subi r2 = r2, 4
+//     sw r2[0] = reg
+// .endm
+pub fn expand(lines: &[Line], errors: &mut Errors) -> Vec<Line> {
+    expand_limited(lines, MAX_EXPANDED_LINES, errors)
+}
+
+// 展開で作る行を limit 行までとする
+fn expand_limited(lines: &[Line], limit: usize, errors: &mut Errors) -> Vec<Line> {
+    let mut macros = HashMap::new();
+    let mut body_lines = Vec::new();
+
+    let mut iter = lines.iter();
+    while let Some(line) = iter.next() {
+        if is_directive(line, ".endm") {
+            errors.push(Error::new(ErrorKind::UnexpectedEndm, line.tokens[0].span));
+            continue;
+        }
+        if !is_directive(line, ".macro") {
+            body_lines.push(line);
+            continue;
+        }
+
+        // .endm までを本体とする
+        let header = parse_header(line);
+        let mut body = Vec::new();
+        let mut closed = false;
+        for line in iter.by_ref() {
+            if is_directive(line, ".endm") {
+                closed = true;
+                break;
+            }
+            if is_directive(line, ".macro") {
+                errors.push(Error::new(ErrorKind::NestedMacro, line.tokens[0].span));
+                continue;
+            }
+            body.push(line.clone());
+        }
+
+        let (name, name_span, params) = match header {
+            Ok(header) => header,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+        if !closed {
+            errors.push(Error::new(ErrorKind::UnterminatedMacro(name), name_span));
+            continue;
+        }
+        match macros.entry(name) {
+            Entry::Occupied(entry) => errors.push(Error::new(
+                ErrorKind::MacroRedefined(entry.key().clone()),
+                name_span,
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(Macro { params, body });
+            }
+        }
+    }
+
+    let mut expander = Expander {
+        macros: &macros,
+        count: 0,
+        stack: Vec::new(),
+        lines: 0,
+        limit,
+    };
+    let mut result = Vec::new();
+    for line in body_lines {
+        expander.expand_line(line, &mut result, errors);
+    }
+    result
+}
+
+fn is_directive(line: &Line, name: &str) -> bool {
+    matches!(
+        line.tokens.first(),
+        Some(Token { kind: TokenKind::Ident(ident), .. }) if ident == name
+    )
+}
+
+// .macro name param, ...
+fn parse_header(line: &Line) -> Result<(String, Span, Vec<String>), Error> {
+    let mut parser = Parser::new(line);
+    parser.expect_ident()?;
+    let (name, name_span) = parser.expect_ident()?;
+
+    let mut params: Vec<String> = Vec::new();
+    while !parser.is_end() {
+        if !params.is_empty() {
+            parser.expect_punct(",")?;
+        }
+        let (param, span) = parser.expect_ident()?;
+        if params.contains(&param) {
+            return Err(Error::new(ErrorKind::DuplicateMacroParam(param), span));
+        }
+        params.push(param);
+    }
+    Ok((name, name_span, params))
+}
+
+impl Expander<'_> {
+    fn expand_line(&mut self, line: &Line, result: &mut Vec<Line>, errors: &mut Errors) {
+        // 先頭のラベルは読み飛ばす
+        let start = match line.tokens.first() {
+            Some(Token {
+                kind: TokenKind::InstLabel(_),
+                ..
+            }) => 1,
+            _ => 0,
+        };
+        let Some(Token {
+            kind: TokenKind::Ident(name),
+            span,
+        }) = line.tokens.get(start)
+        else {
+            result.push(line.clone());
+            return;
+        };
+        let Some(mac) = self.macros.get(name) else {
+            result.push(line.clone());
+            return;
+        };
+
+        if self.stack.contains(name) {
+            errors.push(Error::new(ErrorKind::RecursiveMacro(name.clone()), *span));
+            return;
+        }
+        let args = match split_args(&line.tokens[start + 1..], line.eol) {
+            Ok(args) => args,
+            Err(err) => {
+                errors.push(err);
+                return;
+            }
+        };
+        if args.len() != mac.params.len() {
+            let end = line.tokens.last().map(|token| token.span).unwrap_or(*span);
+            errors.push(Error::new(
+                ErrorKind::MacroArgCount {
+                    name: name.clone(),
+                    expect: mac.params.len(),
+                    found: args.len(),
+                },
+                span.to(end),
+            ));
+            return;
+        }
+
+        // 呼び出し側のラベルは展開結果の先頭に付ける
+        if start == 1 {
+            result.push(Line {
+                tokens: vec![line.tokens[0].clone()],
+                eol: line.eol,
+                expansion: line.expansion.clone(),
+            });
+        }
+
+        // 展開した行には, ソースに書かれた呼び出しの行の位置を付ける (入れ子の展開では最も外側のもの)
+        let call = match &line.expansion {
+            Some((call, _)) => *call,
+            None => line.tokens[0]
+                .span
+                .to(line.tokens[line.tokens.len() - 1].span),
+        };
+
+        // マクロ内で定義されたラベルは展開ごとに別の名前にする
+        // (数字だけのラベルは前後の近いものを指すので, そのままでよい)
+        self.count += 1;
+        let suffix = format!("#{}", self.count);
+        let locals = mac
+            .body
+            .iter()
+            .filter_map(|line| match line.tokens.first() {
+                Some(Token {
+                    kind: TokenKind::InstLabel(label),
+                    ..
+                }) if !is_numeric(label) => Some(label.clone()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        self.stack.push(name.clone());
+        for body_line in &mac.body {
+            // 上限を超えたら, 最初に超えた呼び出しだけを報告して以降は展開しない
+            if self.lines >= self.limit {
+                if self.lines == self.limit {
+                    let kind = ErrorKind::MacroExpansionTooLarge(self.limit);
+                    errors.push(Error::new(kind, call));
+                    self.lines += 1;
+                }
+                break;
+            }
+            self.lines += 1;
+            let mut tokens = Vec::new();
+            for token in &body_line.tokens {
+                match &token.kind {
+                    TokenKind::Ident(ident) => match mac.params.iter().position(|p| p == ident) {
+                        Some(idx) => tokens.extend(args[idx].iter().cloned()),
+                        None => tokens.push(token.clone()),
+                    },
+                    TokenKind::InstLabel(label) if locals.contains(label) => tokens.push(Token {
+                        kind: TokenKind::InstLabel(format!("{}{}", label, suffix)),
+                        span: token.span,
+                    }),
+                    _ => tokens.push(token.clone()),
+                }
+            }
+            // リスティングには引数だけを置き換えた行を書く
+            let substituted = body_line
+                .tokens
+                .iter()
+                .flat_map(|token| match &token.kind {
+                    TokenKind::Ident(ident) => match mac.params.iter().position(|p| p == ident) {
+                        Some(idx) => args[idx].clone(),
+                        None => vec![token.clone()],
+                    },
+                    _ => vec![token.clone()],
+                })
+                .collect::<Vec<_>>();
+            let line = Line {
+                tokens,
+                eol: body_line.eol,
+                expansion: Some((call, render(&substituted))),
+            };
+            self.expand_line(&line, result, errors);
+        }
+        self.stack.pop();
+    }
+}
+
+// 呼び出しの引数を "," で区切る (括弧の中の "," は区切らない)
+fn split_args(tokens: &[Token], eol: Span) -> Result<Vec<Vec<Token>>, Error> {
+    if tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut args = vec![Vec::new()];
+    let mut depth = 0usize;
+    for token in tokens {
+        match token.kind {
+            TokenKind::Punct(",") if depth == 0 => {
+                if args.last().is_some_and(Vec::is_empty) {
+                    return Err(empty_arg(",", token.span));
+                }
+                args.push(Vec::new());
+                continue;
+            }
+            TokenKind::Punct("(") => depth += 1,
+            TokenKind::Punct(")") => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        if let Some(arg) = args.last_mut() {
+            arg.push(token.clone());
+        }
+    }
+    if args.last().is_some_and(Vec::is_empty) {
+        return Err(empty_arg("end of line", eol));
+    }
+    Ok(args)
+}
+
+fn empty_arg(found: &str, span: Span) -> Error {
+    Error::new(
+        ErrorKind::UnexpectedToken {
+            expect: "macro argument".to_string(),
+            found: found.to_string(),
+        },
+        span,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use super::expand_limited;
+    use crate::error::Errors;
+    use crate::lex::lex;
+    use crate::object::Section;
+    use crate::source::SourceMap;
+    use crate::{assemble, assemble_object, Options};
+
+    fn error_kinds(source: &str) -> Vec<String> {
+        let err = assemble(source, 6).unwrap_err();
+        let errors = err.downcast::<Errors>().unwrap();
+        errors.iter().map(|err| err.kind.to_string()).collect()
+    }
+
+    #[test]
+    fn local_labels() {
+        let source = ".text
+.macro wait n
+    li r1 = n
+@loop
+    subi r1 = r1, 1
+    bne r0, (r1, r0) -> @loop
+.endm
+@main
+    wait 3
+    wait 5
+";
+        let expected = ".text
+@main
+    li r1 = 3
+@loop1
+    subi r1 = r1, 1
+    bne r0, (r1, r0) -> @loop1
+    li r1 = 5
+@loop2
+    subi r1 = r1, 1
+    bne r0, (r1, r0) -> @loop2
+";
+        assert_eq!(assemble(source, 6).unwrap(), assemble(expected, 6).unwrap());
+
+        let files = HashMap::from([(PathBuf::from("main.asm"), source.to_string())]);
+        let mut sources = SourceMap::new();
+        let options = Options::default();
+        let object =
+            assemble_object(Path::new("main.asm"), &files, &mut sources, &options).unwrap();
+        let labels = object
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.section == Section::Text)
+            .map(|symbol| (symbol.name.as_str(), symbol.offset))
+            .collect::<Vec<_>>();
+        assert_eq!(labels, [("main", 0), ("loop#1", 6), ("loop#2", 24)]);
+    }
+
+    #[test]
+    fn params() {
+        // 引数はトークン列のまま置き換える (ラベル, 式, レジスタ)
+        let source = ".data
+$table
+    byte4 1, 2, 3
+.text
+.macro load dst, addr
+    lw dst = r0[addr]
+.endm
+.macro branch_if_zero reg, target
+    beq r0, (reg, r0) -> target
+.endm
+@start
+    load r3, $table + 4
+    load r4, (1 + 2) * 4
+    branch_if_zero r3, @start
+    branch_if_zero r4, @start + 6
+";
+        let expected = ".data
+$table
+    byte4 1, 2, 3
+.text
+@start
+    lw r3 = r0[$table + 4]
+    lw r4 = r0[(1 + 2) * 4]
+    beq r0, (r3, r0) -> @start
+    beq r0, (r4, r0) -> @start + 6
+";
+        assert_eq!(assemble(source, 6).unwrap(), assemble(expected, 6).unwrap());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error_kinds(".text\n    nop\n.endm\n"),
+            [".endm without .macro"]
+        );
+        assert_eq!(
+            error_kinds(".text\n.macro push reg\n    sw r2[0] = reg\n"),
+            ["Unterminated macro definition: push (missing .endm)"]
+        );
+        assert_eq!(
+            error_kinds(
+                ".text\n.macro push reg\n    sw r2[0] = reg\n.endm\n    push r1, r2\n    push\n"
+            ),
+            [
+                "Macro push takes 1 argument(s) but 2 were given",
+                "Macro push takes 1 argument(s) but 0 were given",
+            ]
+        );
+        assert_eq!(
+            error_kinds(".text\n.macro a\n    b\n.endm\n.macro b\n    a\n.endm\n    a\n"),
+            ["Recursive macro invocation: a"]
+        );
+    }
+
+    #[test]
+    fn expansion_too_large() {
+        // 1 つ前のマクロを 2 回ずつ呼ぶと, 展開した行の数は倍々になる
+        let mut source = String::from(".text\n.macro m0\n    nop\n.endm\n");
+        for idx in 1..=64 {
+            source += &format!(".macro m{}\n    m{1}\n    m{1}\n.endm\n", idx, idx - 1);
+        }
+        let expand = |call: &str| {
+            let mut errors = Errors::new(None);
+            let lines = lex(&format!("{}{}", source, call), 0, &mut errors);
+            let lines = expand_limited(&lines, 1000, &mut errors);
+            let kinds = errors
+                .iter()
+                .map(|err| err.kind.to_string())
+                .collect::<Vec<_>>();
+            (lines.len(), kinds)
+        };
+        // m7 を 1 回展開すると 2 + 4 + ... + 128 = 254 行 (.text の行と nop 128 行ずつが残る)
+        assert_eq!(expand("    m7\n    m7\n"), (1 + 256, vec![]));
+        let (len, kinds) = expand("    m64\n    m64\n");
+        assert!(len < 1000);
+        assert_eq!(kinds, ["Macro expansion produces more than 1000 lines"]);
+    }
+}
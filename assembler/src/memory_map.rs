@@ -0,0 +1,255 @@
+use crate::error::{Error, ErrorKind, Errors, Span};
+
+// 命令メモリ・データメモリとその他の領域の先頭アドレスと大きさ
+// ラベルの値やリスティング, 出力のアドレスは先頭アドレスからのものになる
+//
+// # 名前 先頭アドレス [大きさ]
+// imem 0x0000 0x1000
+// dmem 0x8000 0x800
+// uart 0xF000 0x10
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub imem: Region,
+    pub dmem: Region,
+    // データのアドレス空間にある, その他の領域 ($名前 で先頭アドレスを使える)
+    pub regions: Vec<(String, Region)>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Region {
+    pub base: usize,
+    // byte 数 (None: 無制限)
+    pub size: Option<usize>,
+}
+
+// アドレスは 32bit (レジスタと命令の即値の幅)
+const ADDRESS_SPACE: u64 = 1 << 32;
+
+impl Region {
+    // 最後の byte の次のアドレス (大きさが無制限, またはアドレス空間を超えるなら None)
+    fn end(&self) -> Option<usize> {
+        self.size.and_then(|size| self.base.checked_add(size))
+    }
+
+    // 32bit のアドレス空間に収まらないか
+    fn overflows(&self) -> bool {
+        let end = (self.base as u64).checked_add(self.size.unwrap_or(0) as u64);
+        self.base as u64 >= ADDRESS_SPACE || end.is_none_or(|end| end > ADDRESS_SPACE)
+    }
+}
+
+impl MemoryMap {
+    pub fn get_mut(&mut self, name: &str) -> &mut Region {
+        match name {
+            "imem" => &mut self.imem,
+            "dmem" => &mut self.dmem,
+            _ => match self.regions.iter().position(|(region, _)| region == name) {
+                Some(idx) => &mut self.regions[idx].1,
+                None => {
+                    self.regions.push((name.to_string(), Region::default()));
+                    &mut self.regions.last_mut().unwrap().1
+                }
+            },
+        }
+    }
+
+    // アドレス空間に収まらない領域と, データのアドレス空間で重なっている領域があればエラーにする
+    // (命令メモリは別のアドレス空間)
+    pub fn check(&self) -> Result<(), Errors> {
+        let mut errors = Errors::new(None);
+        let regions = [("dmem", &self.dmem)]
+            .into_iter()
+            .chain(
+                self.regions
+                    .iter()
+                    .map(|(name, region)| (name.as_str(), region)),
+            )
+            .collect::<Vec<_>>();
+        for (name, region) in [("imem", &self.imem)].iter().chain(&regions) {
+            if region.overflows() {
+                let reason = format!("region `{}` does not fit in the 32-bit address space", name);
+                errors.push(Error::global(ErrorKind::InvalidMemoryMap(reason)));
+            }
+        }
+
+        for (idx, (a, region_a)) in regions.iter().enumerate() {
+            for (b, region_b) in &regions[(idx + 1)..] {
+                let overlaps = |x: &Region, y: &Region| match x.end() {
+                    Some(end) => x.base <= y.base && y.base < end,
+                    None => x.base == y.base,
+                };
+                if overlaps(region_a, region_b) || overlaps(region_b, region_a) {
+                    errors.push(Error::global(ErrorKind::RegionsOverlap(
+                        a.to_string(),
+                        b.to_string(),
+                    )));
+                }
+            }
+        }
+        errors.into_result()
+    }
+}
+
+// 1 行に 1 つの領域を書いたファイルを読む (# から行末まではコメント)
+pub fn read_memory_map(text: &str) -> Result<MemoryMap, Errors> {
+    let mut map = MemoryMap::default();
+    let mut errors = Errors::new(None);
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim_end();
+        if line.trim().is_empty() {
+            continue;
+        }
+        let span = Span::new(idx + 1, 1, line.len());
+        match read_line(line) {
+            Ok((name, region)) => *map.get_mut(&name) = region,
+            Err(reason) => errors.push(Error::new(ErrorKind::InvalidMemoryMap(reason), span)),
+        }
+    }
+    errors.into_result()?;
+    map.check()?;
+    Ok(map)
+}
+
+fn read_line(line: &str) -> Result<(String, Region), String> {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    let (name, base, size) = match words[..] {
+        [name, base] => (name, base, None),
+        [name, base, size] => (name, base, Some(size)),
+        _ => return Err("expect `<name> <base> [<size>]`".to_string()),
+    };
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid || name.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(format!("invalid region name `{}`", name));
+    }
+    let region = Region {
+        base: parse_number(base)?,
+        size: size.map(parse_number).transpose()?,
+    };
+    Ok((name.to_string(), region))
+}
+
+// 10 進数または 0x で始まる 16 進数
+pub fn parse_number(text: &str) -> Result<usize, String> {
+    let number = match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(&hex.replace('_', ""), 16),
+        None => text.replace('_', "").parse(),
+    };
+    number.map_err(|_| format!("invalid number `{}`", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // エラーの (行, メッセージ)
+    fn errors(result: Result<MemoryMap, Errors>) -> Vec<(usize, String)> {
+        let errors = result.unwrap_err();
+        errors
+            .iter()
+            .map(|err| (err.span.line, err.kind.to_string()))
+            .collect()
+    }
+
+    fn region(base: usize, size: Option<usize>) -> Region {
+        Region { base, size }
+    }
+
+    #[test]
+    fn read() {
+        let map = read_memory_map(
+            "# name base size
+imem 0x0000 0x1000
+dmem 0x8000 2_048   # 2KiB
+
+uart 0xF000 0x10
+gpio 0xF100
+uart 0xF010 0x10
+",
+        )
+        .unwrap();
+        assert_eq!(map.imem, region(0, Some(0x1000)));
+        assert_eq!(map.dmem, region(0x8000, Some(2048)));
+        // 同じ名前は後のもので上書きする
+        assert_eq!(
+            map.regions,
+            [
+                ("uart".to_string(), region(0xF010, Some(0x10))),
+                ("gpio".to_string(), region(0xF100, None)),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_errors() {
+        assert_eq!(
+            errors(read_memory_map(
+                "imem\ndmem 0x10 0x10 0x10\n1st 0\nuart 0xG\nio-port 0\n"
+            )),
+            [
+                (
+                    1,
+                    "Invalid memory map: expect `<name> <base> [<size>]`".to_string()
+                ),
+                (
+                    2,
+                    "Invalid memory map: expect `<name> <base> [<size>]`".to_string()
+                ),
+                (
+                    3,
+                    "Invalid memory map: invalid region name `1st`".to_string()
+                ),
+                (4, "Invalid memory map: invalid number `0xG`".to_string()),
+                (
+                    5,
+                    "Invalid memory map: invalid region name `io-port`".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn overlap() {
+        // 命令メモリは別のアドレス空間なので重なってよい
+        assert!(read_memory_map("imem 0 0x1000\ndmem 0 0x1000\nuart 0x1000 0x10\n").is_ok());
+        assert_eq!(
+            errors(read_memory_map(
+                "dmem 0 0x1000\nuart 0xFFF 0x10\ngpio 0x1000\n"
+            )),
+            [
+                (0, "memory regions dmem and uart overlap".to_string()),
+                (0, "memory regions uart and gpio overlap".to_string()),
+            ]
+        );
+        // 大きさのない領域は先頭アドレスが同じときだけ重なる
+        assert!(read_memory_map("uart 0x100\ngpio 0x101\n").is_ok());
+        assert!(read_memory_map("uart 0x100\ngpio 0x100\n").is_err());
+    }
+
+    #[test]
+    fn address_space() {
+        let map = MemoryMap {
+            imem: region(0xFFFF_F000, Some(0x1000)),
+            dmem: region(0, Some(0xFFFF_FFFF)),
+            regions: vec![("top".to_string(), region(0xFFFF_FFFF, Some(1)))],
+        };
+        assert!(map.check().is_ok());
+
+        let map = MemoryMap {
+            imem: region(0xFFFF_F000, Some(0x1001)),
+            dmem: region(1 << 32, None),
+            regions: vec![("huge".to_string(), region(usize::MAX, Some(usize::MAX)))],
+        };
+        let errors = map.check().unwrap_err();
+        let errors = errors
+            .iter()
+            .map(|err| err.kind.to_string())
+            .collect::<Vec<_>>();
+        let reason = |name| {
+            format!(
+                "Invalid memory map: region `{}` does not fit in the 32-bit address space",
+                name
+            )
+        };
+        assert_eq!(errors, [reason("imem"), reason("dmem"), reason("huge")]);
+    }
+}
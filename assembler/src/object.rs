@@ -0,0 +1,568 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::error::{Error, ErrorKind, Errors, Span};
+use crate::expr::{BinOp, Expr, UnOp, MAX_DEPTH};
+
+// 1 つのソースをアセンブルした結果 (リンクして data.hex / inst.hex にする)
+// セクションのアドレスはリンクするまで決まらないので, ラベルを含む式は再配置情報として残す
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    // Span::file が指すソースファイル
+    pub files: Vec<PathBuf>,
+    pub data: Vec<u8>,
+    // 命令語 (6 byte ずつ, 再配置するフィールドは 0 にしてある)
+    pub text: Vec<u8>,
+    pub symbols: Vec<Symbol>,
+    pub relocs: Vec<Reloc>,
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Section {
+    Data,
+    Text,
+}
+
+// ラベルの定義 (offset はセクションの先頭からの byte)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Section,
+    pub offset: usize,
+    // .global で宣言したもの (他のオブジェクトからも使える)
+    pub global: bool,
+    pub span: Span,
+}
+
+// データ・命令 1 つ分の位置と, それを書いたソースの位置 (リスティング用)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub section: Section,
+    pub offset: usize,
+    pub len: usize,
+    pub span: Span,
+    // マクロの展開で作られたものは, 呼び出しの位置と引数を置き換えた行
+    pub expansion: Option<(Span, String)>,
+}
+
+// section の offset の位置にあるフィールドを expr の値で埋める
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reloc {
+    pub section: Section,
+    pub offset: usize,
+    pub kind: RelocKind,
+    pub expr: Expr,
+    pub span: Span,
+    // エラーに添える補足 (擬似命令の展開結果)
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocKind {
+    Byte(usize), // byte1, byte2, byte4, byte6
+    Imm,         // I 形式の imm (32bit)
+    Offset,      // J, L, S 形式の imm (符号付き 32bit)
+    Branch,      // B 形式の imm (符号付き 25bit, 命令のアドレスなら pc からの相対値)
+}
+
+impl RelocKind {
+    // フィールドに入る値の範囲
+    // (32bit の即値とデータは符号付き・符号なしのどちらの範囲も受け付ける)
+    pub fn range(self) -> (i64, i64) {
+        match self {
+            RelocKind::Byte(len) => (-(1 << (len * 8 - 1)), (1 << (len * 8)) - 1),
+            RelocKind::Imm => (i32::MIN as i64, u32::MAX as i64),
+            RelocKind::Offset => (i32::MIN as i64, i32::MAX as i64),
+            RelocKind::Branch => (-(1 << 24), (1 << 24) - 1),
+        }
+    }
+
+    // フィールドを含むデータ・命令語の byte 数
+    pub fn size(self) -> usize {
+        match self {
+            RelocKind::Byte(len) => len,
+            _ => 6,
+        }
+    }
+
+    // bytes (フィールドを含むデータ, または命令語) に value を書き込む
+    pub fn patch(self, bytes: &mut [u8], value: i64) {
+        let (shift, mask) = match self {
+            RelocKind::Byte(len) => {
+                bytes[..len].copy_from_slice(&value.to_le_bytes()[..len]);
+                return;
+            }
+            RelocKind::Imm | RelocKind::Offset => (16, 0xFFFF_FFFF),
+            RelocKind::Branch => (23, 0x1FF_FFFF),
+        };
+        let mut word = [0; 8];
+        word[..6].copy_from_slice(&bytes[..6]);
+        let word = u64::from_le_bytes(word) & !(mask << shift) | ((value as u64 & mask) << shift);
+        bytes[..6].copy_from_slice(&word.to_le_bytes()[..6]);
+    }
+
+    fn name(self) -> String {
+        match self {
+            RelocKind::Byte(len) => format!("byte{}", len),
+            RelocKind::Imm => "imm".to_string(),
+            RelocKind::Offset => "offset".to_string(),
+            RelocKind::Branch => "branch".to_string(),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<RelocKind> {
+        match name {
+            "byte1" => Some(RelocKind::Byte(1)),
+            "byte2" => Some(RelocKind::Byte(2)),
+            "byte4" => Some(RelocKind::Byte(4)),
+            "byte6" => Some(RelocKind::Byte(6)),
+            "imm" => Some(RelocKind::Imm),
+            "offset" => Some(RelocKind::Offset),
+            "branch" => Some(RelocKind::Branch),
+            _ => None,
+        }
+    }
+}
+
+impl Object {
+    pub fn section(&self, section: Section) -> &[u8] {
+        match section {
+            Section::Data => &self.data,
+            Section::Text => &self.text,
+        }
+    }
+}
+
+impl Section {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Section::Data => "data",
+            Section::Text => "text",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Section> {
+        match name {
+            "data" => Some(Section::Data),
+            "text" => Some(Section::Text),
+            _ => None,
+        }
+    }
+}
+
+// オブジェクトファイルの書式 (1 行に 1 項目)
+//
+// sbobj 1
+// file main.asm
+// data 48656C6C6F00
+// text 2200000000000000...
+// symbol data 0 local 0:2:1:4 msg
+// symbol text 0 global 0:5:1:5 main
+// reloc text 0 imm 0:3:15:4 $msg
+// note pseudo-instruction `la` expands to `addi r10 = r0, $msg`
+// item text 0 6 0:3:5:14
+// item text 6 6 0:2:5:15 0:8:5:9 sw r2[0] = r3
+const MAGIC: &str = "sbobj 1";
+
+// data, text 1 行あたりの byte 数
+const BYTES_PER_LINE: usize = 32;
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", MAGIC)?;
+        for path in &self.files {
+            writeln!(f, "file {}", path.display())?;
+        }
+        for (name, bytes) in [("data", &self.data), ("text", &self.text)] {
+            for chunk in bytes.chunks(BYTES_PER_LINE) {
+                let hex = chunk.iter().map(|byte| format!("{:02X}", byte));
+                writeln!(f, "{} {}", name, hex.collect::<String>())?;
+            }
+        }
+        for symbol in &self.symbols {
+            writeln!(
+                f,
+                "symbol {} {} {} {} {}",
+                symbol.section.name(),
+                symbol.offset,
+                if symbol.global { "global" } else { "local" },
+                Location(symbol.span),
+                symbol.name
+            )?;
+        }
+        for reloc in &self.relocs {
+            writeln!(
+                f,
+                "reloc {} {} {} {} {}",
+                reloc.section.name(),
+                reloc.offset,
+                reloc.kind.name(),
+                Location(reloc.span),
+                Prefix(&reloc.expr)
+            )?;
+            if let Some(note) = &reloc.note {
+                writeln!(f, "note {}", note)?;
+            }
+        }
+        for item in &self.items {
+            write!(
+                f,
+                "item {} {} {} {}",
+                item.section.name(),
+                item.offset,
+                item.len,
+                Location(item.span)
+            )?;
+            match &item.expansion {
+                Some((call, text)) => writeln!(f, " {} {}", Location(*call), text)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// file:line:col:len
+struct Location(Span);
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Span {
+            file,
+            line,
+            col,
+            len,
+        } = self.0;
+        write!(f, "{}:{}:{}:{}", file, line, col, len)
+    }
+}
+
+// 式を前置記法で書く (ラベル名に使える文字をそのまま残すため)
+// ($table + 4) * 2 -> * + $table 4 2
+struct Prefix<'a>(&'a Expr);
+
+impl fmt::Display for Prefix<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Expr::Num(num) => write!(f, "{}", num),
+            Expr::DataLabel(label) => write!(f, "${}", label),
+            Expr::InstLabel(label) => write!(f, "@{}", label),
+            Expr::Unary(UnOp::Neg, expr) => write!(f, "neg {}", Prefix(expr)),
+            Expr::Unary(UnOp::Not, expr) => write!(f, "not {}", Prefix(expr)),
+            Expr::Binary(op, lhs, rhs) => {
+                write!(f, "{} {} {}", op.punct(), Prefix(lhs), Prefix(rhs))
+            }
+        }
+    }
+}
+
+// オブジェクトファイルを読む (エラーの位置は text の行)
+pub fn read_object(text: &str) -> Result<Object, Errors> {
+    let mut object = Object::default();
+    let mut errors = Errors::new(None);
+
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty());
+    if lines.next().map(|(_, line)| line) != Some(MAGIC) {
+        let span = Span::new(1, 1, text.lines().next().unwrap_or("").len());
+        let err = Error::new(
+            ErrorKind::InvalidObject(format!("expect `{}`", MAGIC)),
+            span,
+        );
+        return Err(err.into());
+    }
+    // symbol, reloc, item を書いた行 (セクションに収まるかを最後に調べるため)
+    let (mut symbols, mut relocs, mut items) = (Vec::new(), Vec::new(), Vec::new());
+    for (idx, line) in lines {
+        let span = Span::new(idx + 1, 1, line.len());
+        if let Err(reason) = read_line(&mut object, line) {
+            errors.push(Error::new(ErrorKind::InvalidObject(reason), span));
+        }
+        for (spans, len) in [
+            (&mut symbols, object.symbols.len()),
+            (&mut relocs, object.relocs.len()),
+            (&mut items, object.items.len()),
+        ] {
+            spans.resize(len, span);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let ranges = (object.symbols.iter().zip(&symbols))
+        .map(|(symbol, span)| (symbol.section, symbol.offset, 0, span))
+        .chain(
+            (object.relocs.iter().zip(&relocs))
+                .map(|(reloc, span)| (reloc.section, reloc.offset, reloc.kind.size(), span)),
+        )
+        .chain(
+            (object.items.iter().zip(&items))
+                .map(|(item, span)| (item.section, item.offset, item.len, span)),
+        );
+    for (section, offset, len, span) in ranges {
+        let section_len = object.section(section).len();
+        if offset.checked_add(len).is_none_or(|end| end > section_len) {
+            let reason = format!(
+                "offset {} is out of the {} section ({} bytes)",
+                offset,
+                section.name(),
+                section_len
+            );
+            errors.push(Error::new(ErrorKind::InvalidObject(reason), *span));
+        }
+    }
+    errors.into_result()?;
+    Ok(object)
+}
+
+fn read_line(object: &mut Object, line: &str) -> Result<(), String> {
+    let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let mut words = rest.split_whitespace();
+    let mut next = |what: &str| words.next().ok_or(format!("missing {}", what));
+    match key {
+        "file" => object.files.push(PathBuf::from(rest)),
+        "data" => object.data.extend(read_bytes(rest)?),
+        "text" => object.text.extend(read_bytes(rest)?),
+        "symbol" => {
+            let section = read_section(next("section")?)?;
+            let offset = read_num(next("offset")?)?;
+            let global = match next("binding")? {
+                "global" => true,
+                "local" => false,
+                binding => return Err(format!("unknown binding `{}`", binding)),
+            };
+            let span = read_span(next("span")?)?;
+            let name = next("name")?.to_string();
+            object.symbols.push(Symbol {
+                name,
+                section,
+                offset,
+                global,
+                span,
+            });
+        }
+        "reloc" => {
+            let section = read_section(next("section")?)?;
+            let offset = read_num(next("offset")?)?;
+            let kind = next("kind")?;
+            let kind = RelocKind::from_name(kind).ok_or(format!("unknown kind `{}`", kind))?;
+            let span = read_span(next("span")?)?;
+            let expr = read_expr(&mut words, 0)?;
+            if let Some(word) = words.next() {
+                return Err(format!("unexpected `{}`", word));
+            }
+            object.relocs.push(Reloc {
+                section,
+                offset,
+                kind,
+                expr,
+                span,
+                note: None,
+            });
+        }
+        "item" => {
+            // 展開した行は空白を含むので, 最後の項目は行末までとする
+            let mut fields = rest.splitn(6, ' ');
+            let mut next = |what: &str| fields.next().ok_or(format!("missing {}", what));
+            let section = read_section(next("section")?)?;
+            let offset = read_num(next("offset")?)?;
+            let len = read_num(next("length")?)?;
+            let span = read_span(next("span")?)?;
+            let expansion = match fields.next() {
+                Some(call) => Some((read_span(call)?, fields.next().unwrap_or("").to_string())),
+                None => None,
+            };
+            object.items.push(Item {
+                section,
+                offset,
+                len,
+                span,
+                expansion,
+            });
+        }
+        "note" => match object.relocs.last_mut() {
+            Some(reloc) => reloc.note = Some(rest.to_string()),
+            None => return Err("note without reloc".to_string()),
+        },
+        _ => return Err(format!("unknown record `{}`", key)),
+    }
+    Ok(())
+}
+
+fn read_bytes(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(format!("invalid hex `{}`", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&hex[idx..(idx + 2)], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid hex `{}`", hex))
+}
+
+fn read_section(word: &str) -> Result<Section, String> {
+    Section::from_name(word).ok_or(format!("unknown section `{}`", word))
+}
+
+fn read_num<T: std::str::FromStr>(word: &str) -> Result<T, String> {
+    word.parse()
+        .map_err(|_| format!("invalid number `{}`", word))
+}
+
+// file:line:col:len
+fn read_span(word: &str) -> Result<Span, String> {
+    let nums = word
+        .split(':')
+        .map(read_num)
+        .collect::<Result<Vec<usize>, _>>()?;
+    match nums[..] {
+        [file, line, col, len] => Ok(Span::new(line, col, len).with_file(file)),
+        _ => Err(format!("invalid span `{}`", word)),
+    }
+}
+
+// depth は外側の式の数 (MAX_DEPTH より深い式はエラー)
+fn read_expr<'a>(words: &mut impl Iterator<Item = &'a str>, depth: usize) -> Result<Expr, String> {
+    if depth >= MAX_DEPTH {
+        return Err(format!(
+            "expression is nested more than {} levels",
+            MAX_DEPTH
+        ));
+    }
+    let word = words.next().ok_or("missing expression")?;
+    if let Some(label) = word.strip_prefix('$') {
+        return Ok(Expr::DataLabel(label.to_string()));
+    }
+    if let Some(label) = word.strip_prefix('@') {
+        return Ok(Expr::InstLabel(label.to_string()));
+    }
+    let op = match word {
+        "neg" => Some(UnOp::Neg),
+        "not" => Some(UnOp::Not),
+        _ => None,
+    };
+    if let Some(op) = op {
+        return Ok(Expr::Unary(op, Box::new(read_expr(words, depth + 1)?)));
+    }
+    if let Some(op) = BinOp::from_punct(word) {
+        let lhs = read_expr(words, depth + 1)?;
+        let rhs = read_expr(words, depth + 1)?;
+        return Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)));
+    }
+    Ok(Expr::Num(read_num(word)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::source::SourceMap;
+    use crate::{assemble_object, Options};
+
+    fn compile(source: &str) -> Object {
+        let files = HashMap::from([(PathBuf::from("main.asm"), source.to_string())]);
+        let mut sources = SourceMap::new();
+        assemble_object(
+            Path::new("main.asm"),
+            &files,
+            &mut sources,
+            &Options::default(),
+        )
+        .unwrap()
+    }
+
+    // エラーの (行, 理由)
+    fn read_errors(text: &str) -> Vec<(usize, String)> {
+        let errors = read_object(text).unwrap_err();
+        errors
+            .iter()
+            .map(|err| match &err.kind {
+                ErrorKind::InvalidObject(reason) => (err.span.line, reason.clone()),
+                kind => panic!("{:?}", kind),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let object = compile(
+            ".text
+.global @main
+.extern @print
+.macro push reg
+    subi r2 = r2, 4
+    sw r2[0] = reg
+.endm
+.data
+$msg
+    string \"Hi\"
+$ptr
+    byte4 ($msg + 1) * 2, -3
+.text
+@main
+    la r10 = $msg
+    push r3
+    call @print
+    beq r0, (r1, r0) -> @main
+",
+        );
+        assert!(object.relocs.iter().any(|reloc| reloc.note.is_some()));
+        assert!(object.items.iter().any(|item| item.expansion.is_some()));
+        assert_eq!(read_object(&object.to_string()).unwrap(), object);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            read_errors("sbobj 2\n"),
+            [(1, "expect `sbobj 1`".to_string())]
+        );
+        assert_eq!(
+            read_errors("sbobj 1\nfoo bar\ndata 0\nsymbol data x local 0:1:1:1 a\n"),
+            [
+                (2, "unknown record `foo`".to_string()),
+                (3, "invalid hex `0`".to_string()),
+                (4, "invalid number `x`".to_string()),
+            ]
+        );
+
+        // セクションに収まらない位置 (足すとあふれるものも)
+        let text = "sbobj 1
+data 0000
+text 000000000000
+symbol data 3 local 0:1:1:1 a
+symbol text 6 local 0:1:1:1 end
+reloc text 18446744073709551615 branch 0:1:1:1 @end
+reloc data 0 byte4 0:1:1:1 $a
+item text 0 6 0:1:1:1
+item text 6 6 0:1:1:1
+";
+        assert_eq!(
+            read_errors(text),
+            [
+                (
+                    4,
+                    "offset 3 is out of the data section (2 bytes)".to_string()
+                ),
+                (
+                    6,
+                    "offset 18446744073709551615 is out of the text section (6 bytes)".to_string()
+                ),
+                (
+                    7,
+                    "offset 0 is out of the data section (2 bytes)".to_string()
+                ),
+                (
+                    9,
+                    "offset 6 is out of the text section (6 bytes)".to_string()
+                ),
+            ]
+        );
+    }
+}
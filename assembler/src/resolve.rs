@@ -1,77 +1,184 @@
 use crate::imem::ir::{unresolved, resolved};
-use crate::dmem::ir::Data;
+use crate::dmem::ir::{Command, Data};
+use crate::error::{Error, ErrorKind, Errors, Span};
+use crate::expr::Expr;
+use crate::linkage::Linkage;
+use crate::object::{Item, Reloc, RelocKind, Section, Symbol};
 use std::collections::HashMap;
 
-pub fn resolve(datas: &[Data], insts: Vec<unresolved::Inst>) -> anyhow::Result<Vec<resolved::Inst>> {
-    let mut data_label_map = HashMap::new();
+// 1 つのソースのラベルの位置 (各セクションの先頭からの byte) を決め, ラベルを含まない式を計算する
+// ラベルを含む式はフィールドを 0 にしておき, リンク時に計算するよう再配置情報に残す
+// (data_end, inst_end は各セクションの終わりを指すラベル)
+pub fn resolve(datas: &[Data], data_end: &[(String, Span)], insts: Vec<unresolved::Inst>, inst_end: &[(String, Span)], linkage: &Linkage, errors: &mut Errors) -> (Vec<u8>, Vec<resolved::Inst>, Vec<Symbol>, Vec<Reloc>) {
+    let mut symbols = Vec::new();
     let mut current_addr = 0;
     for data in datas {
-        if data.label.is_some() {
-            data_label_map.insert(data.label.clone().unwrap(), current_addr);
+        for (label, span) in &data.labels {
+            let global = linkage.is_global('$', label);
+            symbols.push(Symbol { name: label.clone(), section: Section::Data, offset: current_addr, global, span: *span });
         }
         current_addr += data.command.len();
     }
+    for (label, span) in data_end {
+        let global = linkage.is_global('$', label);
+        symbols.push(Symbol { name: label.clone(), section: Section::Data, offset: current_addr, global, span: *span });
+    }
+
+    let inst_labels = insts.iter().enumerate().flat_map(|(idx, inst)| inst.labels.iter().map(move |label| (idx, label)));
+    let end_labels = inst_end.iter().map(|label| (insts.len(), label));
+    for (idx, (label, span)) in inst_labels.chain(end_labels) {
+        let global = linkage.is_global('@', label);
+        symbols.push(Symbol { name: label.clone(), section: Section::Text, offset: idx*6, global, span: *span });
+    }
 
-    let mut inst_label_map = HashMap::new();
-    for (idx, inst) in insts.iter().enumerate() { 
-        if inst.label.is_some() {
-            inst_label_map.insert(inst.label.clone().unwrap(), idx*6);
+    let mut relocs = Vec::new();
+
+    // データ
+    let mut bytes = Vec::new();
+    for data in datas {
+        let mut fields = Fields { relocs: &mut relocs, section: Section::Data, offset: bytes.len(), span: data.span, note: None };
+        match fields.resolve_data(&data.command) {
+            Ok(data_bytes) => bytes.extend(data_bytes),
+            Err(err) => errors.push(err),
         }
     }
 
-    let calc_diff = |value: &unresolved::Value, pos: i64| -> i32 {
-        if let unresolved::Value::InstLabel(label) = value {
-            let imm = (*inst_label_map.get(label).unwrap() as i64) - pos * 6;
-            imm as i32
-        } else if let unresolved::Value::Imm(imm) = value {
-            *imm as i32
-        } else {
-            unreachable!();
+    // 命令
+    let mut resolved_insts = Vec::new();
+    for (idx, inst) in insts.into_iter().enumerate() {
+        let span = inst.arg_spans.imm.unwrap_or(inst.span);
+        let note = inst.note();
+        let mut fields = Fields { relocs: &mut relocs, section: Section::Text, offset: idx*6, span, note: note.clone() };
+        match resolve_inst(inst.kind, &mut fields) {
+            Ok(converted) => resolved_insts.push(converted),
+            Err(err) => errors.push(err.with_note(note)),
         }
-    };
-    
-    let calc_imm = |value: &unresolved::Value| -> u32 {
-        if let unresolved::Value::Imm(imm) = value {
-            *imm as u32
-        } else if let unresolved::Value::InstLabel(label) = value {
-            *inst_label_map.get(label).unwrap() as u32
-        } else if let unresolved::Value::DataLabel(label) = value {
-            *data_label_map.get(label).unwrap() as u32
-        } else {
-            unreachable!();
+    }
+
+    (bytes, resolved_insts, symbols, relocs)
+}
+
+// . で始まるラベル (@.loop) は直前の . が 1 つ少ないラベルの中だけのもので, 定義・参照とも
+// 外側のラベルを付けた名前 (func_print.loop) にする (外側のラベルがなければそのまま)
+// 数字だけのラベルとマクロの展開で作られたラベル (loop#1) は範囲を区切らない
+pub fn scoped_labels(insts: &mut [unresolved::Inst], end_labels: &mut [(String, Span)]) {
+    // 深さごとの外側のラベル
+    let mut scopes: Vec<String> = Vec::new();
+    for inst in insts.iter_mut() {
+        for (label, _) in &mut inst.labels {
+            define_scoped(&mut scopes, label);
         }
-    };
+        if let Some(expr) = inst.kind.expr_mut() {
+            expr.rename_inst_labels(&mut |label| (depth(label) > 0).then(|| qualify(&scopes, label)));
+        }
+    }
+    for (label, _) in end_labels {
+        define_scoped(&mut scopes, label);
+    }
+}
 
-    let mut resolved_insts = Vec::new();
-    for (idx, inst) in insts.into_iter().enumerate() { 
+// ラベルの定義を外側のラベルを付けた名前にし, 以降のラベルの外側にする
+fn define_scoped(scopes: &mut Vec<String>, label: &mut String) {
+    let depth = depth(label);
+    if depth > 0 || !(is_numeric(label) || label.contains('#')) {
+        scopes.truncate(depth);
+        *label = qualify(scopes, label);
+        if scopes.len() == depth && !label.contains('#') {
+            scopes.push(label.clone());
+        }
+    }
+}
+
+fn depth(label: &str) -> usize {
+    label.len() - label.trim_start_matches('.').len()
+}
+
+fn qualify(scopes: &[String], label: &str) -> String {
+    match depth(label).checked_sub(1).and_then(|idx| scopes.get(idx)) {
+        Some(scope) => format!("{}.{}", scope, label.trim_start_matches('.')),
+        None => label.to_string(),
+    }
+}
+
+// 数字だけのラベル (@1) は何度でも定義でき, @1f / @1b でその命令より後 / 前 (自身を含む) の最も近い定義を指す
+// 定義ごとに 1#1, 1#2, ... と名前を付け直して参照を置き換える (対応する定義がない参照はそのまま残す)
+// (命令部の終わりのラベルは最後の命令より後にあるものとする)
+pub fn numeric_labels(insts: &mut [unresolved::Inst], end_labels: &mut [(String, Span)]) {
+    // 番号 -> 定義した命令の位置
+    let mut defs: HashMap<String, Vec<usize>> = HashMap::new();
+    let len = insts.len();
+    let inst_labels = insts.iter_mut().enumerate().flat_map(|(idx, inst)| inst.labels.iter_mut().map(move |label| (idx, label)));
+    let end_labels = end_labels.iter_mut().map(|label| (len, label));
+    for (idx, (label, _)) in inst_labels.chain(end_labels) {
+        if is_numeric(label) {
+            let positions = defs.entry(label.clone()).or_default();
+            positions.push(idx);
+            *label = format!("{}#{}", label, positions.len());
+        }
+    }
+
+    for (idx, inst) in insts.iter_mut().enumerate() {
+        let Some(expr) = inst.kind.expr_mut() else { continue };
+        expr.rename_inst_labels(&mut |label| {
+            let (num, nth) = if let Some(num) = label.strip_suffix('f') {
+                (num, defs.get(num)?.iter().position(|&pos| pos > idx)?)
+            } else if let Some(num) = label.strip_suffix('b') {
+                (num, defs.get(num)?.iter().rposition(|&pos| pos <= idx)?)
+            } else {
+                return None;
+            };
+            Some(format!("{}#{}", num, nth + 1))
+        });
+    }
+}
+
+pub fn is_numeric(label: &str) -> bool {
+    !label.is_empty() && label.bytes().all(|b| b.is_ascii_digit())
+}
+
+// 各データ・命令の位置と長さ (byte)
+pub fn items(datas: &[Data], insts: &[unresolved::Inst]) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut current_addr = 0;
+    for data in datas {
+        items.push(Item { section: Section::Data, offset: current_addr, len: data.command.len(), span: data.span, expansion: None });
+        current_addr += data.command.len();
+    }
+    for (idx, inst) in insts.iter().enumerate() {
+        items.push(Item { section: Section::Text, offset: idx*6, len: 6, span: inst.span, expansion: inst.expansion.clone() });
+    }
+    items
+}
+
+fn resolve_inst(kind: unresolved::InstKind, fields: &mut Fields) -> Result<resolved::Inst, Error> {
         #[rustfmt::skip]
-        let converted = match inst.kind {
+        let converted = match kind {
             unresolved::InstKind::Add { rd, rs1, rs2 } => resolved::Inst::Add { rd, rs1, rs2 },
             unresolved::InstKind::Sub { rd, rs1, rs2 } => resolved::Inst::Sub { rd, rs1, rs2 },
 
-            unresolved::InstKind::Addi { rd, rs1, val } => resolved::Inst::Addi { rd, rs1, imm: calc_imm(&val) },
-            unresolved::InstKind::Subi { rd, rs1, val } => resolved::Inst::Subi { rd, rs1, imm: calc_imm(&val) },
-
-            unresolved::InstKind::Beq { rd, rs1, rs2, val } => resolved::Inst::Beq { rd, rs1, rs2, imm: calc_diff(&val, idx as i64) },
-            unresolved::InstKind::Ble { rd, rs1, rs2, val } => resolved::Inst::Ble { rd, rs1, rs2, imm: calc_diff(&val, idx as i64) },
-            unresolved::InstKind::Blt { rd, rs1, rs2, val } => resolved::Inst::Blt { rd, rs1, rs2, imm: calc_diff(&val, idx as i64) },
-            unresolved::InstKind::Bne { rd, rs1, rs2, val } => resolved::Inst::Bne { rd, rs1, rs2, imm: calc_diff(&val, idx as i64) },
-            unresolved::InstKind::Jal { rd, rs1, imm } => resolved::Inst::Jal { rd, rs1, imm },
-
-            unresolved::InstKind::Lw { rd, rs1, imm } => resolved::Inst::Lw { rd, rs1, imm },
-            unresolved::InstKind::Lh { rd, rs1, imm } => resolved::Inst::Lh { rd, rs1, imm },
-            unresolved::InstKind::Lb { rd, rs1, imm } => resolved::Inst::Lb { rd, rs1, imm },
-            unresolved::InstKind::Lhu { rd, rs1, imm } => resolved::Inst::Lhu { rd, rs1, imm },
-            unresolved::InstKind::Lbu { rd, rs1, imm } => resolved::Inst::Lbu { rd, rs1, imm },
-
-            unresolved::InstKind::Sw { rs1, rs2, imm } => resolved::Inst::Sw { rs1, rs2, imm },
-            unresolved::InstKind::Sh { rs1, rs2, imm } => resolved::Inst::Sh { rs1, rs2, imm },
-            unresolved::InstKind::Sb { rs1, rs2, imm } => resolved::Inst::Sb { rs1, rs2, imm },
-            unresolved::InstKind::Isb { rs1, rs2, imm } => resolved::Inst::Isb {rs1, rs2, imm },
-
-            unresolved::InstKind::In { rd, rs1, imm } => resolved::Inst::In { rd, rs1, imm },
-            unresolved::InstKind::Out { rs1, rs2, imm } => resolved::Inst::Out { rs1, rs2, imm },
-            
+            unresolved::InstKind::Addi { rd, rs1, val } => resolved::Inst::Addi { rd, rs1, imm: fields.calc_imm(&val)? },
+            unresolved::InstKind::Subi { rd, rs1, val } => resolved::Inst::Subi { rd, rs1, imm: fields.calc_imm(&val)? },
+
+            unresolved::InstKind::Beq { rd, rs1, rs2, val } => resolved::Inst::Beq { rd, rs1, rs2, imm: fields.calc_diff(&val)? },
+            unresolved::InstKind::Ble { rd, rs1, rs2, val } => resolved::Inst::Ble { rd, rs1, rs2, imm: fields.calc_diff(&val)? },
+            unresolved::InstKind::Blt { rd, rs1, rs2, val } => resolved::Inst::Blt { rd, rs1, rs2, imm: fields.calc_diff(&val)? },
+            unresolved::InstKind::Bne { rd, rs1, rs2, val } => resolved::Inst::Bne { rd, rs1, rs2, imm: fields.calc_diff(&val)? },
+            unresolved::InstKind::Jal { rd, rs1, imm } => resolved::Inst::Jal { rd, rs1, imm: fields.calc_offset(&imm)? },
+
+            unresolved::InstKind::Lw { rd, rs1, imm } => resolved::Inst::Lw { rd, rs1, imm: fields.calc_offset(&imm)? },
+            unresolved::InstKind::Lh { rd, rs1, imm } => resolved::Inst::Lh { rd, rs1, imm: fields.calc_offset(&imm)? },
+            unresolved::InstKind::Lb { rd, rs1, imm } => resolved::Inst::Lb { rd, rs1, imm: fields.calc_offset(&imm)? },
+            unresolved::InstKind::Lhu { rd, rs1, imm } => resolved::Inst::Lhu { rd, rs1, imm: fields.calc_offset(&imm)? },
+            unresolved::InstKind::Lbu { rd, rs1, imm } => resolved::Inst::Lbu { rd, rs1, imm: fields.calc_offset(&imm)? },
+
+            unresolved::InstKind::Sw { rs1, rs2, imm } => resolved::Inst::Sw { rs1, rs2, imm: fields.calc_offset(&imm)? },
+            unresolved::InstKind::Sh { rs1, rs2, imm } => resolved::Inst::Sh { rs1, rs2, imm: fields.calc_offset(&imm)? },
+            unresolved::InstKind::Sb { rs1, rs2, imm } => resolved::Inst::Sb { rs1, rs2, imm: fields.calc_offset(&imm)? },
+            unresolved::InstKind::Isb { rs1, rs2, imm } => resolved::Inst::Isb { rs1, rs2, imm: fields.calc_offset(&imm)? },
+
+            unresolved::InstKind::In { rd, rs1, imm } => resolved::Inst::In { rd, rs1, imm: fields.calc_offset(&imm)? },
+            unresolved::InstKind::Out { rs1, rs2, imm } => resolved::Inst::Out { rs1, rs2, imm: fields.calc_offset(&imm)? },
+
             unresolved::InstKind::And { rd, rs1, rs2 } => resolved::Inst::And { rd, rs1, rs2 },
             unresolved::InstKind::Or { rd, rs1, rs2 } => resolved::Inst::Or { rd, rs1, rs2 },
             unresolved::InstKind::Xor { rd, rs1, rs2 } => resolved::Inst::Xor { rd, rs1, rs2 },
@@ -79,17 +186,79 @@ pub fn resolve(datas: &[Data], insts: Vec<unresolved::Inst>) -> anyhow::Result<V
             unresolved::InstKind::Sra { rd, rs1, rs2 } => resolved::Inst::Sra { rd, rs1, rs2 },
             unresolved::InstKind::Sll { rd, rs1, rs2 } => resolved::Inst::Sll { rd, rs1, rs2 },
 
-            unresolved::InstKind::Andi { rd, rs1, val } => resolved::Inst::Andi { rd, rs1, imm: calc_imm(&val) },
-            unresolved::InstKind::Ori { rd, rs1, val } => resolved::Inst::Ori { rd, rs1, imm: calc_imm(&val) },
-            unresolved::InstKind::Xori { rd, rs1, val } => resolved::Inst::Xori { rd, rs1, imm: calc_imm(&val) },
-            unresolved::InstKind::Srli { rd, rs1, val } => resolved::Inst::Srli { rd, rs1, imm: calc_imm(&val) },
-            unresolved::InstKind::Srai { rd, rs1, val } => resolved::Inst::Srai { rd, rs1, imm: calc_imm(&val) },
-            unresolved::InstKind::Slli { rd, rs1, val } => resolved::Inst::Slli { rd, rs1, imm: calc_imm(&val) },
+            unresolved::InstKind::Andi { rd, rs1, val } => resolved::Inst::Andi { rd, rs1, imm: fields.calc_imm(&val)? },
+            unresolved::InstKind::Ori { rd, rs1, val } => resolved::Inst::Ori { rd, rs1, imm: fields.calc_imm(&val)? },
+            unresolved::InstKind::Xori { rd, rs1, val } => resolved::Inst::Xori { rd, rs1, imm: fields.calc_imm(&val)? },
+            unresolved::InstKind::Srli { rd, rs1, val } => resolved::Inst::Srli { rd, rs1, imm: fields.calc_imm(&val)? },
+            unresolved::InstKind::Srai { rd, rs1, val } => resolved::Inst::Srai { rd, rs1, imm: fields.calc_imm(&val)? },
+            unresolved::InstKind::Slli { rd, rs1, val } => resolved::Inst::Slli { rd, rs1, imm: fields.calc_imm(&val)? },
+    };
+    Ok(converted)
+}
+
+// 1 つのデータ・命令のフィールドの値を計算する
+struct Fields<'a> {
+    relocs: &'a mut Vec<Reloc>,
+    section: Section,
+    offset: usize,
+    span: Span,
+    note: Option<String>,
+}
+
+impl Fields<'_> {
+    // ラベルを含まなければ計算して範囲を確かめ, 含むなら再配置情報を残して 0 にする
+    fn calc(&mut self, expr: &Expr, kind: RelocKind) -> Result<i64, Error> {
+        if !expr.labels().is_empty() {
+            self.relocs.push(Reloc {
+                section: self.section,
+                offset: self.offset,
+                kind,
+                expr: expr.clone(),
+                span: self.span,
+                note: self.note.clone(),
+            });
+            return Ok(0);
+        }
+        let imm = expr
+            .eval(&HashMap::new(), &HashMap::new())
+            .map_err(|kind| Error::new(kind, self.span))?;
+        check_range(imm, kind.range(), self.span)
+    }
+
+    fn calc_diff(&mut self, expr: &Expr) -> Result<i32, Error> {
+        Ok(self.calc(expr, RelocKind::Branch)? as i32)
+    }
+
+    fn calc_imm(&mut self, expr: &Expr) -> Result<u32, Error> {
+        Ok(self.calc(expr, RelocKind::Imm)? as u32)
+    }
+
+    fn calc_offset(&mut self, expr: &Expr) -> Result<i32, Error> {
+        Ok(self.calc(expr, RelocKind::Offset)? as i32)
+    }
+
+    // リトルエンディアンの byte 列にする
+    fn resolve_data(&mut self, command: &Command) -> Result<Vec<u8>, Error> {
+        let mut value = |expr: &Expr, len: usize| -> Result<Vec<u8>, Error> {
+            let value = self.calc(expr, RelocKind::Byte(len))?;
+            Ok(value.to_le_bytes()[..len].to_vec())
         };
-        resolved_insts.push(converted);
+        match command {
+            Command::Byte1(expr) => value(expr, 1),
+            Command::Byte2(expr) => value(expr, 2),
+            Command::Byte4(expr) => value(expr, 4),
+            Command::Byte6(expr) => value(expr, 6),
+            Command::Char(c) => Ok(vec![*c as u8]),
+            Command::String(s) => Ok(s.bytes().chain([0]).collect()),
+        }
     }
+}
 
-    Ok(resolved_insts)
+pub fn check_range(imm: i64, (min, max): (i64, i64), span: Span) -> Result<i64, Error> {
+    if !(min..=max).contains(&imm) {
+        return Err(Error::new(ErrorKind::ImmOverflow { imm, min, max }, span));
+    }
+    Ok(imm)
 }
 
 /*
@@ -119,3 +288,78 @@ label    kind
 [noname] beq r0, (r2, r3) -> 0-48       48
 [noname] beq r0, (r0, r0) -> 12-54      54
 */
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use crate::object::{Object, Section};
+    use crate::source::SourceMap;
+    use crate::{assemble_object, Options};
+
+    fn compile(source: &str) -> Object {
+        let files = HashMap::from([(PathBuf::from("main.asm"), source.to_string())]);
+        let mut sources = SourceMap::new();
+        assemble_object(Path::new("main.asm"), &files, &mut sources, &Options::default()).unwrap()
+    }
+
+    fn offset(object: &Object, section: Section, name: &str) -> Option<usize> {
+        let symbol = object.symbols.iter().find(|symbol| symbol.section == section && symbol.name == name)?;
+        Some(symbol.offset)
+    }
+
+    #[test]
+    fn stacked_scoped_labels() {
+        let object = compile(
+            ".text
+@main
+    call @func_print
+@func_print
+@.loop
+    beq r0, (r5, r0) -> @..end
+    j @.loop
+@..end
+    ret
+",
+        );
+        assert_eq!(offset(&object, Section::Text, "main"), Some(0));
+        assert_eq!(offset(&object, Section::Text, "func_print"), Some(6));
+        assert_eq!(offset(&object, Section::Text, "func_print.loop"), Some(6));
+        assert_eq!(offset(&object, Section::Text, "func_print.loop.end"), Some(18));
+    }
+
+    #[test]
+    fn stacked_numeric_labels() {
+        let object = compile(
+            ".text
+@1
+@2
+    nop
+    j @1b
+    j @2b
+",
+        );
+        assert_eq!(offset(&object, Section::Text, "1#1"), Some(0));
+        assert_eq!(offset(&object, Section::Text, "2#1"), Some(0));
+    }
+
+    #[test]
+    fn labels_at_end_of_section() {
+        let object = compile(
+            "$msg
+    string \"ab\"
+$msg_end
+.text
+@main
+    la r1 = $msg_end
+    j @end
+@.end
+@end
+",
+        );
+        assert_eq!(offset(&object, Section::Data, "msg_end"), Some(3));
+        assert_eq!(offset(&object, Section::Text, "main.end"), Some(12));
+        assert_eq!(offset(&object, Section::Text, "end"), Some(12));
+    }
+}
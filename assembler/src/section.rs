@@ -0,0 +1,180 @@
+use crate::error::{Error, Errors};
+use crate::lex::{Line, Token, TokenKind};
+use crate::syntax::Parser;
+
+// 行をデータ部と命令部に振り分ける (それぞれ出てきた順につなげる)
+// .data / .text は何度でも切り替えられ, include したファイルにも引き継がれる
+// (最初はデータ部で, === は .text と同じ)
+//
+// .data
+// $msg
+//     string "Hello"
+// .text
+//     la r10 = $msg
+pub fn split(lines: &[Line], errors: &mut Errors) -> Option<(Vec<Line>, Vec<Line>)> {
+    let mut data_lines = Vec::new();
+    let mut inst_lines = Vec::new();
+    let mut in_text = false;
+    let mut found = false;
+
+    for line in lines {
+        let section = match &line.tokens[..] {
+            [Token {
+                kind: TokenKind::Punct("==="),
+                ..
+            }] => Some(true),
+            [Token {
+                kind: TokenKind::Ident(ident),
+                ..
+            }, ..]
+                if ident == ".data" || ident == ".text" =>
+            {
+                if let Err(err) = parse_directive(line) {
+                    errors.push(err);
+                }
+                Some(ident == ".text")
+            }
+            _ => None,
+        };
+        match section {
+            Some(text) => {
+                in_text = text;
+                found = true;
+            }
+            None if in_text => inst_lines.push(line.clone()),
+            None => data_lines.push(line.clone()),
+        }
+    }
+
+    // 区切りが 1 つもなければ None
+    found.then_some((data_lines, inst_lines))
+}
+
+// .data / .text は引数を取らない
+fn parse_directive(line: &Line) -> Result<(), Error> {
+    let mut parser = Parser::new(line);
+    parser.expect_ident()?;
+    parser.expect_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::lex::{lex, render};
+    use crate::{assemble, assemble_image, Options, SourceMap};
+
+    fn split_source(source: &str, errors: &mut Errors) -> Option<(Vec<String>, Vec<String>)> {
+        let lines = lex(source, 0, errors);
+        let render_all = |lines: Vec<Line>| lines.iter().map(|line| render(&line.tokens)).collect();
+        split(&lines, errors).map(|(data, inst)| (render_all(data), render_all(inst)))
+    }
+
+    #[test]
+    fn switch_sections() {
+        let source = "$a\n    byte1 1\n.text\n    nop\n.data\n$b\n    byte1 2\n.text\n    ret\n";
+        let mut errors = Errors::new(None);
+        let (data, inst) = split_source(source, &mut errors).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(data, ["$a", "byte1 1", "$b", "byte1 2"]);
+        assert_eq!(inst, ["nop", "ret"]);
+    }
+
+    #[test]
+    fn no_separator() {
+        let mut errors = Errors::new(None);
+        assert!(split_source("$a\n    byte1 1\n", &mut errors).is_none());
+        assert!(errors.is_empty());
+
+        let err = assemble("$a\n    byte1 1\n", 6).unwrap_err();
+        let errors = err.downcast::<Errors>().unwrap();
+        let kinds = errors.iter().map(|err| &err.kind).collect::<Vec<_>>();
+        assert!(matches!(kinds[..], [ErrorKind::SeparatorNotFound]));
+    }
+
+    #[test]
+    fn directive_arguments() {
+        // 引数はエラーにして, 区切りとしては扱う
+        let mut errors = Errors::new(None);
+        let (_, inst) = split_source(".text 1\n    nop\n", &mut errors).unwrap();
+        assert_eq!(inst, ["nop"]);
+        assert_eq!(errors.iter().count(), 1);
+    }
+
+    #[test]
+    fn legacy_separator() {
+        let legacy = "$msg\n    string \"Hi\"\n===\n    la r10 = $msg\n    ret\n";
+        let directives = ".data\n$msg\n    string \"Hi\"\n.text\n    la r10 = $msg\n    ret\n";
+        assert_eq!(
+            assemble(legacy, 6).unwrap(),
+            assemble(directives, 6).unwrap()
+        );
+    }
+
+    #[test]
+    fn interleaved() {
+        // 各セクションを出てきた順につなげたものと同じになる
+        let interleaved = "
+.text
+@main
+    la r2 = $msg
+    call @print
+    j @main
+.data
+$msg
+    string \"Hello\"
+.text
+@print
+    lb r1 = r2[0]
+    ret
+.data
+$count
+    byte4 $msg + 2
+";
+        let concatenated = "
+$msg
+    string \"Hello\"
+$count
+    byte4 $msg + 2
+===
+@main
+    la r2 = $msg
+    call @print
+    j @main
+@print
+    lb r1 = r2[0]
+    ret
+";
+        assert_eq!(
+            assemble(interleaved, 6).unwrap(),
+            assemble(concatenated, 6).unwrap()
+        );
+    }
+
+    #[test]
+    fn included_section() {
+        // include したファイルの中の切り替えは, include した後の行にも引き継がれる
+        let files = HashMap::from([
+            (
+                PathBuf::from("main.asm"),
+                ".text\n    nop\ninclude \"msg.asm\"\n$end\n    byte1 0\n".to_string(),
+            ),
+            (
+                PathBuf::from("msg.asm"),
+                ".data\n$msg\n    byte1 1\n".to_string(),
+            ),
+            (
+                PathBuf::from("flat.asm"),
+                "$msg\n    byte1 1\n$end\n    byte1 0\n===\n    nop\n".to_string(),
+            ),
+        ]);
+        let image = |path: &str| {
+            let mut sources = SourceMap::new();
+            assemble_image(Path::new(path), &files, &mut sources, &Options::default()).unwrap()
+        };
+        assert_eq!(image("main.asm"), image("flat.asm"));
+    }
+}
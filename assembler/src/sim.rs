@@ -0,0 +1,386 @@
+use thiserror::Error;
+
+use crate::convert::{encode, read_hex};
+use crate::disasm::decode;
+use crate::error::Errors;
+use crate::imem::ir::resolved::Inst;
+
+// メモリの大きさ (byte)
+pub const DMEM_SIZE: usize = 0x10000;
+pub const IMEM_SIZE: usize = 0x10000;
+
+// in/out 命令の入出力先
+pub trait Io {
+    fn input(&mut self, port: u32) -> u32;
+    fn output(&mut self, port: u32, value: u32);
+}
+
+#[derive(Debug, Error)]
+pub enum Trap {
+    #[error("instruction fetch out of range: pc {pc:#X}")]
+    FetchOutOfRange { pc: u32 },
+    #[error("invalid instruction {word:#014X}: pc {pc:#X}")]
+    InvalidInst { pc: u32, word: u64 },
+    #[error("memory access out of range: address {addr:#X}, pc {pc:#X}")]
+    MemOutOfRange { pc: u32, addr: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    // 自分自身への分岐 (無限ループ) で停止したとみなす
+    Halted,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sim {
+    pub regs: [u32; 32],
+    pub pc: u32,
+    pub dmem: Vec<u8>,
+    pub imem: Vec<u8>,
+    pub steps: u64,
+}
+
+impl Sim {
+    // 足りない分は 0 で埋める
+    pub fn new(mut dmem: Vec<u8>, mut imem: Vec<u8>) -> Sim {
+        if dmem.len() < DMEM_SIZE {
+            dmem.resize(DMEM_SIZE, 0);
+        }
+        if imem.len() < IMEM_SIZE {
+            imem.resize(IMEM_SIZE, 0);
+        }
+        Sim {
+            regs: [0; 32],
+            pc: 0,
+            dmem,
+            imem,
+            steps: 0,
+        }
+    }
+
+    pub fn from_insts(dmem: Vec<u8>, insts: &[Inst]) -> anyhow::Result<Sim> {
+        let mut imem = Vec::new();
+        for inst in insts {
+            imem.extend_from_slice(&encode(inst)?.to_le_bytes()[..6]);
+        }
+        Ok(Sim::new(dmem, imem))
+    }
+
+    // assemble の出力 (data.hex, inst.hex) から読み込む
+    pub fn from_hex(data_hex: &str, inst_hex: &str, chunk_size: usize) -> Result<Sim, Errors> {
+        let dmem = read_hex(data_hex, chunk_size)?;
+        let imem = read_hex(inst_hex, chunk_size)?;
+        Ok(Sim::new(dmem, imem))
+    }
+
+    // 停止するか max_steps 命令を実行するまで進める
+    pub fn run(&mut self, io: &mut dyn Io, max_steps: u64) -> Result<Status, Trap> {
+        for _ in 0..max_steps {
+            if self.step(io)? == Status::Halted {
+                return Ok(Status::Halted);
+            }
+        }
+        Ok(Status::Running)
+    }
+
+    // 1 命令実行する
+    pub fn step(&mut self, io: &mut dyn Io) -> Result<Status, Trap> {
+        let pc = self.pc;
+        let inst = self.fetch()?;
+        let next = pc.wrapping_add(6);
+        let mut new_pc = next;
+
+        let regs = self.regs;
+        let r = |reg: u8| regs[reg as usize];
+        let (rd, value) = match inst {
+            Inst::Add { rd, rs1, rs2 } => (rd, r(rs1).wrapping_add(r(rs2))),
+            Inst::Sub { rd, rs1, rs2 } => (rd, r(rs1).wrapping_sub(r(rs2))),
+
+            Inst::Addi { rd, rs1, imm } => (rd, r(rs1).wrapping_add(imm)),
+            Inst::Subi { rd, rs1, imm } => (rd, r(rs1).wrapping_sub(imm)),
+
+            // 分岐命令は成立・不成立によらず rd に戻り先を書く
+            // (大小比較は符号付き)
+            Inst::Beq { rd, rs1, rs2, imm }
+            | Inst::Bne { rd, rs1, rs2, imm }
+            | Inst::Blt { rd, rs1, rs2, imm }
+            | Inst::Ble { rd, rs1, rs2, imm } => {
+                let (a, b) = (r(rs1), r(rs2));
+                let taken = match inst {
+                    Inst::Beq { .. } => a == b,
+                    Inst::Bne { .. } => a != b,
+                    Inst::Blt { .. } => (a as i32) < (b as i32),
+                    _ => (a as i32) <= (b as i32),
+                };
+                if taken {
+                    new_pc = pc.wrapping_add(imm as u32);
+                }
+                (rd, next)
+            }
+            Inst::Jal { rd, rs1, imm } => {
+                new_pc = r(rs1).wrapping_add(imm as u32);
+                (rd, next)
+            }
+
+            Inst::Lw { rd, rs1, imm } => (rd, self.load(r(rs1), imm, 4)?),
+            Inst::Lh { rd, rs1, imm } => (rd, self.load(r(rs1), imm, 2)? as u16 as i16 as u32),
+            Inst::Lb { rd, rs1, imm } => (rd, self.load(r(rs1), imm, 1)? as u8 as i8 as u32),
+            Inst::Lhu { rd, rs1, imm } => (rd, self.load(r(rs1), imm, 2)?),
+            Inst::Lbu { rd, rs1, imm } => (rd, self.load(r(rs1), imm, 1)?),
+
+            Inst::Sw { rs1, rs2, imm } => (0, self.store(r(rs1), imm, r(rs2), 4)?),
+            Inst::Sh { rs1, rs2, imm } => (0, self.store(r(rs1), imm, r(rs2), 2)?),
+            Inst::Sb { rs1, rs2, imm } => (0, self.store(r(rs1), imm, r(rs2), 1)?),
+            // 命令メモリへの 1 byte 書き込み
+            Inst::Isb { rs1, rs2, imm } => {
+                let addr = r(rs1).wrapping_add(imm as u32);
+                let byte = r(rs2) as u8;
+                match self.imem.get_mut(addr as usize) {
+                    Some(dst) => *dst = byte,
+                    None => return Err(Trap::MemOutOfRange { pc, addr }),
+                }
+                (0, 0)
+            }
+
+            Inst::In { rd, rs1, imm } => (rd, io.input(r(rs1).wrapping_add(imm as u32))),
+            Inst::Out { rs1, rs2, imm } => {
+                io.output(r(rs1).wrapping_add(imm as u32), r(rs2));
+                (0, 0)
+            }
+
+            Inst::And { rd, rs1, rs2 } => (rd, r(rs1) & r(rs2)),
+            Inst::Or { rd, rs1, rs2 } => (rd, r(rs1) | r(rs2)),
+            Inst::Xor { rd, rs1, rs2 } => (rd, r(rs1) ^ r(rs2)),
+            Inst::Srl { rd, rs1, rs2 } => (rd, r(rs1) >> (r(rs2) & 31)),
+            Inst::Sra { rd, rs1, rs2 } => (rd, ((r(rs1) as i32) >> (r(rs2) & 31)) as u32),
+            Inst::Sll { rd, rs1, rs2 } => (rd, r(rs1) << (r(rs2) & 31)),
+
+            Inst::Andi { rd, rs1, imm } => (rd, r(rs1) & imm),
+            Inst::Ori { rd, rs1, imm } => (rd, r(rs1) | imm),
+            Inst::Xori { rd, rs1, imm } => (rd, r(rs1) ^ imm),
+            Inst::Srli { rd, rs1, imm } => (rd, r(rs1) >> (imm & 31)),
+            Inst::Srai { rd, rs1, imm } => (rd, ((r(rs1) as i32) >> (imm & 31)) as u32),
+            Inst::Slli { rd, rs1, imm } => (rd, r(rs1) << (imm & 31)),
+        };
+
+        // r0 は常に 0
+        if rd != 0 {
+            self.regs[rd as usize] = value;
+        }
+        self.pc = new_pc;
+        self.steps += 1;
+
+        if new_pc == pc {
+            Ok(Status::Halted)
+        } else {
+            Ok(Status::Running)
+        }
+    }
+
+    fn fetch(&self) -> Result<Inst, Trap> {
+        let pc = self.pc;
+        let bytes = (pc as usize)
+            .checked_add(6)
+            .and_then(|end| self.imem.get(pc as usize..end))
+            .ok_or(Trap::FetchOutOfRange { pc })?;
+        let word = bytes
+            .iter()
+            .rev()
+            .fold(0, |word, byte| (word << 8) | *byte as u64);
+        decode(word).ok_or(Trap::InvalidInst { pc, word })
+    }
+
+    // リトルエンディアンで len byte 読む (ゼロ拡張)
+    fn load(&self, base: u32, imm: i32, len: usize) -> Result<u32, Trap> {
+        let bytes = self.mem_range(base, imm, len)?;
+        Ok(self.dmem[bytes]
+            .iter()
+            .rev()
+            .fold(0, |value, byte| (value << 8) | *byte as u32))
+    }
+
+    // 書き込み先のレジスタはないので 0 を返す
+    fn store(&mut self, base: u32, imm: i32, value: u32, len: usize) -> Result<u32, Trap> {
+        let bytes = self.mem_range(base, imm, len)?;
+        self.dmem[bytes].copy_from_slice(&value.to_le_bytes()[..len]);
+        Ok(0)
+    }
+
+    fn mem_range(&self, base: u32, imm: i32, len: usize) -> Result<std::ops::Range<usize>, Trap> {
+        let addr = base.wrapping_add(imm as u32);
+        let start = addr as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= self.dmem.len() => Ok(start..end),
+            _ => Err(Trap::MemOutOfRange { pc: self.pc, addr }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::source::SourceMap;
+    use crate::{assemble_image, Options};
+
+    // in は port * 10 を返し, out は記録する
+    #[derive(Default)]
+    struct TestIo {
+        outputs: Vec<(u32, u32)>,
+    }
+
+    impl Io for TestIo {
+        fn input(&mut self, port: u32) -> u32 {
+            port * 10
+        }
+
+        fn output(&mut self, port: u32, value: u32) {
+            self.outputs.push((port, value));
+        }
+    }
+
+    fn load(source: &str) -> Sim {
+        let files = HashMap::from([(PathBuf::from("main.asm"), source.to_string())]);
+        let mut sources = SourceMap::new();
+        let options = Options::default();
+        let (data, text) =
+            assemble_image(Path::new("main.asm"), &files, &mut sources, &options).unwrap();
+        Sim::new(data, text)
+    }
+
+    // 停止するまで実行する
+    fn run(source: &str) -> (Sim, TestIo) {
+        let mut sim = load(source);
+        let mut io = TestIo::default();
+        assert_eq!(sim.run(&mut io, 1000).unwrap(), Status::Halted);
+        (sim, io)
+    }
+
+    #[test]
+    fn link_registers() {
+        let (sim, _) = run(".text
+    beq r5, (r0, r0) -> @target
+@back
+    bne r8, (r0, r0) -> @back
+@halt
+    j @halt
+@target
+    jal r6, r0[@back]
+");
+        // 分岐・ジャンプは成立するかによらず次の命令のアドレスを書く
+        assert_eq!(sim.regs[5], 6);
+        assert_eq!(sim.regs[6], 24);
+        assert_eq!(sim.regs[8], 12);
+        assert_eq!(sim.pc, 12);
+        assert_eq!(sim.steps, 4);
+    }
+
+    #[test]
+    fn halt() {
+        let mut sim = load(".text\n    nop\n@halt\n    j @halt\n");
+        let mut io = TestIo::default();
+        assert_eq!(sim.run(&mut io, 1).unwrap(), Status::Running);
+        assert_eq!(sim.step(&mut io).unwrap(), Status::Halted);
+        assert_eq!((sim.pc, sim.steps), (6, 2));
+    }
+
+    #[test]
+    fn sign_extension() {
+        let (sim, _) = run(".data
+$half
+    byte2 0x80F1
+.text
+    lh r1 = r0[$half]
+    lhu r2 = r0[$half]
+    lb r3 = r0[$half]
+    lbu r4 = r0[$half]
+    lb r5 = r0[$half + 1]
+    lw r6 = r0[$half]
+@halt
+    j @halt
+");
+        assert_eq!(
+            sim.regs[1..7],
+            [0xFFFF_80F1, 0x80F1, 0xFFFF_FFF1, 0xF1, 0xFFFF_FF80, 0x80F1]
+        );
+    }
+
+    #[test]
+    fn shifts() {
+        let (sim, _) = run(".text
+    li r1 = 0x80000010
+    li r2 = 4
+    srl r3 = r1, r2
+    sra r4 = r1, r2
+    srli r5 = r1, 4
+    srai r6 = r1, 4
+    sll r7 = r1, r2
+    slli r8 = r1, 36
+@halt
+    j @halt
+");
+        assert_eq!(
+            sim.regs[3..9],
+            [
+                0x0800_0001,
+                0xF800_0001,
+                0x0800_0001,
+                0xF800_0001,
+                0x100,
+                0x100
+            ]
+        );
+    }
+
+    #[test]
+    fn isb() {
+        // 命令語の 2 byte 目からが即値
+        let (sim, _) = run(".text
+    li r1 = 7
+    isb r0[@patch + 2] = r1
+@patch
+    addi r3 = r0, 1
+@halt
+    j @halt
+");
+        assert_eq!(sim.regs[3], 7);
+        assert_eq!(sim.imem[12 + 2], 7);
+        assert_eq!(sim.dmem.iter().filter(|&&byte| byte != 0).count(), 0);
+    }
+
+    #[test]
+    fn io() {
+        let (sim, io) = run(".text
+    li r2 = 1
+    in r1 = r2[2]
+    out r2[4] = r1
+    out r0[0] = r2
+@halt
+    j @halt
+");
+        assert_eq!(sim.regs[1], 30);
+        assert_eq!(io.outputs, [(5, 30), (0, 1)]);
+    }
+
+    #[test]
+    fn memory_out_of_range() {
+        let mut io = TestIo::default();
+        for (source, addr) in [
+            ("lw r1 = r0[0x10000]", 0x10000),
+            ("sw r0[0xFFFE] = r1", 0xFFFE),
+            ("lb r1 = r0[-1]", 0xFFFF_FFFF),
+            ("isb r0[0x10000] = r1", 0x10000),
+        ] {
+            let mut sim = load(&format!(".text\n    nop\n    {}\n", source));
+            sim.step(&mut io).unwrap();
+            match sim.step(&mut io) {
+                Err(Trap::MemOutOfRange { pc: 6, addr: found }) => assert_eq!(found, addr),
+                result => panic!("{}: {:?}", source, result),
+            }
+            assert_eq!(sim.pc, 6);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+// ファイルの読み込み方法 (テスト等ではメモリ上のファイルを使う)
+pub trait FileLoader {
+    fn load(&self, path: &Path) -> io::Result<String>;
+}
+
+// 実際のファイルシステムから読む
+pub struct FsLoader;
+
+impl FileLoader for FsLoader {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+// パス -> 内容
+impl FileLoader for HashMap<PathBuf, String> {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        self.get(&normalize(path))
+            .cloned()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub source: String,
+    // include 元のファイル
+    pub parent: Option<usize>,
+}
+
+// 読み込んだファイルの一覧 (Span::file はこの番号)
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    // ファイル 1 つだけのもの
+    pub fn single(path: impl Into<PathBuf>, source: &str) -> SourceMap {
+        let mut sources = SourceMap::new();
+        sources.add(path, source);
+        sources
+    }
+
+    pub fn add(&mut self, path: impl Into<PathBuf>, source: &str) -> usize {
+        self.files.push(SourceFile {
+            path: path.into(),
+            source: source.to_string(),
+            parent: None,
+        });
+        self.files.len() - 1
+    }
+
+    pub fn load(&mut self, loader: &dyn FileLoader, path: &Path) -> io::Result<usize> {
+        let source = loader.load(path)?;
+        Ok(self.add(path, &source))
+    }
+
+    // parent から include されたファイルとして読み込む
+    pub fn include(
+        &mut self,
+        loader: &dyn FileLoader,
+        path: &Path,
+        parent: usize,
+    ) -> io::Result<usize> {
+        let file = self.load(loader, path)?;
+        self.files[file].parent = Some(parent);
+        Ok(file)
+    }
+
+    // file 自身と, それを include しているファイルを内側から順に
+    pub fn ancestors(&self, file: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(Some(file), |&file| self.get(file)?.parent)
+    }
+
+    pub fn get(&self, file: usize) -> Option<&SourceFile> {
+        self.files.get(file)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SourceFile> {
+        self.files.iter()
+    }
+}
+
+// "." と ".." を取り除く (ファイルシステムは参照しない)
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match result.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    result.pop();
+                }
+                Some(Component::RootDir) => {}
+                _ => result.push(".."),
+            },
+            _ => result.push(component),
+        }
+    }
+    result
+}
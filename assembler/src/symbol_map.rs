@@ -0,0 +1,202 @@
+use std::path::PathBuf;
+
+use crate::memory_map::MemoryMap;
+use crate::object::{Object, Section};
+
+// リンク後のラベルのアドレスの一覧 (テストベンチ等から名前で引くため)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    // 外側のラベルを付けた名前 (func_print.loop)
+    pub name: String,
+    pub section: Section,
+    pub address: usize,
+    // 次のラベル (このラベルの中のものを除く) かセクションの終わりまでの byte 数
+    pub size: usize,
+    pub global: bool,
+    // 定義したソースファイル
+    pub file: Option<PathBuf>,
+}
+
+impl SymbolMap {
+    // オブジェクトを link と同じ順に並べたときのアドレスで作る (アドレスは map の各メモリの先頭から)
+    pub fn new(objects: &[Object], map: &MemoryMap) -> SymbolMap {
+        let mut entries = Vec::new();
+        let (mut data_base, mut text_base) = (map.dmem.base, map.imem.base);
+        for object in objects {
+            for section in [Section::Data, Section::Text] {
+                let (base, len) = match section {
+                    Section::Data => (data_base, object.data.len()),
+                    Section::Text => (text_base, object.text.len()),
+                };
+                // マクロの展開と数字だけのラベルで付け直した名前 (again#2, 1#1) はソースから引けないので含めない
+                let mut symbols = object
+                    .symbols
+                    .iter()
+                    .filter(|symbol| symbol.section == section && !symbol.name.contains('#'))
+                    .collect::<Vec<_>>();
+                symbols.sort_by_key(|symbol| symbol.offset);
+
+                for (idx, symbol) in symbols.iter().enumerate() {
+                    let scope = format!("{}.", symbol.name);
+                    let end = symbols[(idx + 1)..]
+                        .iter()
+                        .find(|next| !next.name.starts_with(&scope))
+                        .map_or(len, |next| next.offset);
+                    entries.push(Entry {
+                        name: symbol.name.clone(),
+                        section,
+                        address: base + symbol.offset,
+                        size: end.saturating_sub(symbol.offset),
+                        global: symbol.global,
+                        file: object.files.get(symbol.span.file).cloned(),
+                    });
+                }
+            }
+            data_base += object.data.len();
+            text_base += object.text.len();
+        }
+        entries.sort_by_key(|entry| (entry.section == Section::Text, entry.address));
+        SymbolMap { entries }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    // name section address size の表
+    pub fn to_text(&self) -> String {
+        let width = self
+            .entries
+            .iter()
+            .map(|entry| entry.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        let mut result = format!("{:<width$}  section  address     size\n", "name");
+        for entry in &self.entries {
+            result += &format!(
+                "{:<width$}  {:<7}  0x{:08X}  {}\n",
+                entry.name,
+                entry.section.name(),
+                entry.address,
+                entry.size,
+            );
+        }
+        result
+    }
+
+    // [{"name": ..., "section": ..., "address": ..., "size": ..., "global": ..., "file": ...}, ...]
+    pub fn to_json(&self) -> String {
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let file = match &entry.file {
+                    Some(path) => json_string(&path.display().to_string()),
+                    None => "null".to_string(),
+                };
+                format!(
+                    "  {{\"name\": {}, \"section\": \"{}\", \"address\": {}, \"size\": {}, \"global\": {}, \"file\": {}}}",
+                    json_string(&entry.name),
+                    entry.section.name(),
+                    entry.address,
+                    entry.size,
+                    entry.global,
+                    file
+                )
+            })
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            return "[]\n".to_string();
+        }
+        format!("[\n{}\n]\n", entries.join(",\n"))
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            c if (c as u32) < 0x20 => result += &format!("\\u{:04x}", c as u32),
+            c => result.push(c),
+        }
+    }
+    result + "\""
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::{assemble_object, Options, SourceMap};
+
+    fn symbols(source: &str) -> SymbolMap {
+        let files = HashMap::from([(PathBuf::from("main.asm"), source.to_string())]);
+        let mut sources = SourceMap::new();
+        let options = Options::default();
+        let object =
+            assemble_object(Path::new("main.asm"), &files, &mut sources, &options).unwrap();
+        SymbolMap::new(&[object], &MemoryMap::default())
+    }
+
+    const SOURCE: &str = "
+.global @func_main
+$msg
+    string \"Hi\"
+.text
+@func_main
+    nop
+@func_main.loop
+    j @func_main.loop
+@func_print
+@1
+    j @1b
+";
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            symbols(SOURCE).to_text(),
+            "\
+name            section  address     size
+msg             data     0x00000000  3
+func_main       text     0x00000000  12
+func_main.loop  text     0x00000006  6
+func_print      text     0x0000000C  6
+"
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            symbols("$a\n    byte1 1\n.text\n    nop\n").to_json(),
+            "[\n  {\"name\": \"a\", \"section\": \"data\", \"address\": 0, \"size\": 1, \"global\": false, \"file\": \"main.asm\"}\n]\n"
+        );
+        assert_eq!(SymbolMap::default().to_json(), "[]\n");
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+    }
+
+    #[test]
+    fn hidden_names() {
+        // マクロの展開と数字だけのラベルで付け直した名前は含めない
+        let map = symbols(&format!(
+            "{}.macro wait\n@again\n    j @again\n.endm\n    wait\n    wait\n",
+            SOURCE
+        ));
+        assert!(map.entries.iter().all(|entry| !entry.name.contains('#')));
+        assert!(map.get("func_main").unwrap().global);
+        assert!(!map.get("msg").unwrap().global);
+        assert_eq!(map.get("func_print").unwrap().size, 18);
+    }
+}
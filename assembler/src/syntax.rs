@@ -0,0 +1,249 @@
+use crate::error::{Error, ErrorKind, Span};
+use crate::expr::{BinOp, Expr, UnOp, MAX_DEPTH};
+use crate::lex::{Line, Token, TokenKind};
+
+// dmem, imem で共通の 1 行分の構文解析器
+pub struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    eol: Span,
+    // 読んでいる式の再帰の深さ
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(line: &'a Line) -> Parser<'a> {
+        Parser {
+            tokens: &line.tokens,
+            pos: 0,
+            eol: line.eol,
+            depth: 0,
+        }
+    }
+
+    pub fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    pub fn bump(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    // 次のトークンの位置 (行末なら eol)
+    pub fn span(&self) -> Span {
+        self.peek().map(|token| token.span).unwrap_or(self.eol)
+    }
+
+    // 直前に読んだトークンの位置
+    pub fn prev_span(&self) -> Span {
+        self.pos
+            .checked_sub(1)
+            .and_then(|pos| self.tokens.get(pos))
+            .map(|token| token.span)
+            .unwrap_or(self.eol)
+    }
+
+    pub fn unexpected(&self, expect: &str) -> Error {
+        let found = match self.peek() {
+            Some(token) => token.kind.to_string(),
+            None => "end of line".to_string(),
+        };
+        Error::new(
+            ErrorKind::UnexpectedToken {
+                expect: expect.to_string(),
+                found,
+            },
+            self.span(),
+        )
+    }
+
+    pub fn eat_punct(&mut self, punct: &str) -> bool {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Punct(p),
+                ..
+            }) if *p == punct => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn expect_punct(&mut self, punct: &str) -> Result<Span, Error> {
+        if self.eat_punct(punct) {
+            Ok(self.prev_span())
+        } else {
+            Err(self.unexpected(&format!("\"{}\"", punct)))
+        }
+    }
+
+    pub fn expect_ident(&mut self) -> Result<(String, Span), Error> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Ident(ident),
+                span,
+            }) => {
+                self.pos += 1;
+                Ok((ident.clone(), *span))
+            }
+            _ => Err(self.unexpected("identifier")),
+        }
+    }
+
+    pub fn expect_reg(&mut self) -> Result<(u8, Span), Error> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Reg(reg),
+                span,
+            }) => {
+                self.pos += 1;
+                Ok((*reg, *span))
+            }
+            _ => Err(self.unexpected("register")),
+        }
+    }
+
+    pub fn expect_str(&mut self) -> Result<(String, Span), Error> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Str(s),
+                span,
+            }) => {
+                self.pos += 1;
+                Ok((s.clone(), *span))
+            }
+            _ => Err(self.unexpected("string")),
+        }
+    }
+
+    // 式 (二項演算子は優先順位の高いものから結合する)
+    // 入れ子が MAX_DEPTH より深い式はエラーにする
+    pub fn expect_expr(&mut self) -> Result<(Expr, Span), Error> {
+        let start = self.span();
+        let (expr, _) = self.parse_binary(0)?;
+        Ok((expr, start.to(self.prev_span())))
+    }
+
+    // min_prec 以上の優先順位の二項演算子だけを読む
+    // (以下, 読んだ式とその木の深さを返す)
+    fn parse_binary(&mut self, min_prec: u8) -> Result<(Expr, usize), Error> {
+        let (mut lhs, mut depth) = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token {
+                    kind: TokenKind::Punct(punct),
+                    ..
+                }) => BinOp::from_punct(punct),
+                _ => None,
+            };
+            let Some(op) = op.filter(|op| op.prec() >= min_prec) else {
+                return Ok((lhs, depth));
+            };
+            self.pos += 1;
+            let (rhs, rhs_depth) = self.nested(|parser| parser.parse_binary(op.prec() + 1))?;
+            depth = self.deeper(depth.max(rhs_depth))?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<(Expr, usize), Error> {
+        for (punct, op) in [("-", UnOp::Neg), ("~", UnOp::Not)] {
+            if self.eat_punct(punct) {
+                let (expr, depth) = self.nested(Parser::parse_unary)?;
+                return Ok((Expr::Unary(op, Box::new(expr)), self.deeper(depth)?));
+            }
+        }
+        self.parse_primary()
+    }
+
+    // 数値, $label, @label, (expr)
+    fn parse_primary(&mut self) -> Result<(Expr, usize), Error> {
+        let expr = match self.peek() {
+            Some(Token {
+                kind: TokenKind::Num(num),
+                ..
+            }) => Expr::Num(*num),
+            Some(Token {
+                kind: TokenKind::DataLabel(label),
+                ..
+            }) => Expr::DataLabel(label.clone()),
+            Some(Token {
+                kind: TokenKind::InstLabel(label),
+                ..
+            }) => Expr::InstLabel(label.clone()),
+            Some(Token {
+                kind: TokenKind::Punct("("),
+                ..
+            }) => {
+                self.pos += 1;
+                let expr = self.nested(|parser| parser.parse_binary(0))?;
+                self.expect_punct(")")?;
+                return Ok(expr);
+            }
+            // 置き換えられずに残った識別子は未定義の定数
+            Some(Token {
+                kind: TokenKind::Ident(name),
+                span,
+            }) => return Err(Error::new(ErrorKind::ConstNotFound(name.clone()), *span)),
+            _ => return Err(self.unexpected("expression")),
+        };
+        self.pos += 1;
+        Ok((expr, 1))
+    }
+
+    // 入れ子になった式を読む (再帰が MAX_DEPTH より深くなればエラー)
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::new(ErrorKind::ExprTooDeep(MAX_DEPTH), self.span()));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    // 深さ depth の式を 1 段包んだ式の深さ
+    fn deeper(&self, depth: usize) -> Result<usize, Error> {
+        if depth >= MAX_DEPTH {
+            return Err(Error::new(ErrorKind::ExprTooDeep(MAX_DEPTH), self.prev_span()));
+        }
+        Ok(depth + 1)
+    }
+
+    pub fn expect_end(&mut self) -> Result<(), Error> {
+        if self.is_end() {
+            Ok(())
+        } else {
+            Err(self.unexpected("end of line"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assemble;
+
+    fn assemble_imm(expr: &str) -> anyhow::Result<(String, String)> {
+        assemble(&format!("===\n    addi r1 = r0, {}\n", expr), 6)
+    }
+
+    #[test]
+    fn deeply_nested_expr() {
+        let parens = format!("{}1{}", "(".repeat(5000), ")".repeat(5000));
+        assert!(assemble_imm(&parens).is_err());
+        assert!(assemble_imm(&format!("{}1", "-".repeat(5000))).is_err());
+        assert!(assemble_imm(&format!("1{}", " + 1".repeat(5000))).is_err());
+
+        let parens = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+        assert!(assemble_imm(&format!("{} + {}1", parens, "-".repeat(100))).is_ok());
+    }
+}
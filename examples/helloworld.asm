@@ -12,8 +12,8 @@ $helloworld
     beq r1, (r0, r0) -> @func_print
 
     // 無限ループ
-    @loop.func_main
-        beq r0, (r0, r0) -> @loop.func_main
+    @.loop
+        beq r0, (r0, r0) -> @.loop
 
 @func_print
     // フレームポインタの退避
@@ -27,20 +27,20 @@ $helloworld
 
     // 文字列出力ループ
     add r4 = r0, r10
-    @loop.func_print
+    @.loop
         // 文字列取得
         lb r5 = r4[0]
 
         // NULLチェック
-        beq r0, (r5, r0) -> @end.loop.func_print
+        beq r0, (r5, r0) -> @..end
 
         // 出力
         out r0[0] = r5
 
         // カウンタ加算
         addi r4 = r4, 1
-        beq r0, (r0, r0) -> @loop.func_print
-    @end.loop.func_print
+        beq r0, (r0, r0) -> @.loop
+    @..end
 
     // 改行文字出力
     addi r4 = r0, 10
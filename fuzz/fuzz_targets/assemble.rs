@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// 先頭 1 byte を chunk_size, 残りをソースとして与える
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk_size, source)) = data.split_first() else {
+        return;
+    };
+    if let Ok(source) = std::str::from_utf8(source) {
+        let _ = sb_assembler::assemble(source, chunk_size as usize);
+    }
+});
@@ -1,30 +1,634 @@
 use std::env;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
 
-use sb_assembler::assemble;
+use sb_assembler::error::{Error, ErrorKind, Errors};
+use sb_assembler::format::{chisel, coe, ihex, mif, srec, verilog, Radix};
+use sb_assembler::memory_map::{self, Region};
+use sb_assembler::object::Object;
+use sb_assembler::sim::{Io, Sim, Status};
+use sb_assembler::source::{FsLoader, SourceMap};
+use sb_assembler::{
+    assemble_file, assemble_image, assemble_object, disassemble_with, link_image, listing, load_sources,
+    read_hex, read_memory_map, read_object, to_hex, MemoryMap, Options, SymbolMap,
+};
 
 #[rustfmt::skip]
 fn main() {
-    let args = env::args().collect::<Vec<String>>();
+    let mut args = env::args().collect::<Vec<String>>();
+
+    if args.get(1).map(String::as_str) == Some("disassemble") {
+        args.remove(1);
+        disassemble_main(args);
+    } else if args.get(1).map(String::as_str) == Some("run") {
+        args.remove(1);
+        run_main(args);
+    } else if args.get(1).map(String::as_str) == Some("object") {
+        args.remove(1);
+        object_main(args);
+    } else if args.get(1).map(String::as_str) == Some("link") {
+        args.remove(1);
+        link_main(args);
+    } else {
+        assemble_main(args);
+    }
+}
+
+#[rustfmt::skip]
+fn assemble_main(mut args: Vec<String>) {
+    // オプション
+    // 0 は無制限
+    let error_limit = take_option(&mut args, "--error-limit")
+        .and_then(|limit| limit.parse().ok())
+        .filter(|&limit| limit > 0);
+    let output = take_output(&mut args);
+    let extras = take_extras(&mut args);
 
     if args.len() < 4 {
-        println!("Usage: {} [path/to/source] <data.hex> <inst.hex> [<chunk_size>]", args[0]);
+        println!("Usage: {} [path/to/source] <data.hex> <inst.hex> [<chunk_size>] [<output options>] [--error-limit <n>]", args[0]);
+        println!("       {} object [path/to/source] <out.obj> [--error-limit <n>]", args[0]);
+        println!("       {} link <data.hex> <inst.hex> <a.obj>... [--chunk-size <n>] [<output options>] [--error-limit <n>]", args[0]);
+        println!("       {} disassemble [path/to/inst.hex|.ihex|.srec] [<chunk_size>] [--fill <n>]", args[0]);
+        println!("       {} run [path/to/source] [--max-steps <n>]", args[0]);
+        println!("       {} run <data.hex> <inst.hex> [<chunk_size>] [--max-steps <n>]", args[0]);
+        println!();
+        print!("{}", OUTPUT_OPTIONS);
         return;
     }
 
-    let source = fs::read_to_string(&args[1]).unwrap();
     let chunk_size = if args.len() >= 5 {
         args[4].parse().unwrap_or(1)
     } else {
         1
     };
-    let (datas, insts) = assemble(&source, chunk_size).unwrap();
+    let options = output.options(chunk_size, error_limit);
+    let mut sources = SourceMap::new();
+    // リスティングなどを作るときはオブジェクトを経由する
+    let result = if !extras.is_empty() {
+        assemble_object(Path::new(&args[1]), &FsLoader, &mut sources, &options).and_then(|object| {
+            let objects = [object];
+            let (data, text) = link_image(&objects, &options)?;
+            extras.write(&objects, &sources, &data, &text, &options)?;
+            Ok((data, text))
+        })
+    } else {
+        assemble_image(Path::new(&args[1]), &FsLoader, &mut sources, &options)
+    };
+    let (data, text) = match result {
+        Ok(result) => result,
+        Err(err) => report(err, &sources),
+    };
 
     let file_data_path = &args[2];
-    File::create(file_data_path).unwrap().write(datas.as_bytes()).unwrap();
+    if let Err(err) = write_image(file_data_path, data, &output, options.memory_map.dmem, chunk_size) {
+        report(err, &sources);
+    }
 
     let file_inst_path = &args[3];
-    File::create(file_inst_path).unwrap().write(insts.as_bytes()).unwrap();
+    if let Err(err) = write_image(file_inst_path, text, &output, options.memory_map.imem, chunk_size) {
+        report(err, &sources);
+    }
+}
+
+#[rustfmt::skip]
+fn object_main(mut args: Vec<String>) {
+    // オプション
+    let error_limit = take_option(&mut args, "--error-limit")
+        .and_then(|limit| limit.parse().ok())
+        .filter(|&limit| limit > 0);
+    // メモリマップの領域の名前だけを使う
+    let output = take_output(&mut args);
+
+    if args.len() < 3 {
+        println!("Usage: {} object [path/to/source] <out.obj> [--memory-map <file>] [--region <name>=<base>[:<size>]]... [--error-limit <n>]", args[0]);
+        return;
+    }
+
+    let options = output.options(1, error_limit);
+    let mut sources = SourceMap::new();
+    let object = match assemble_object(Path::new(&args[1]), &FsLoader, &mut sources, &options) {
+        Ok(object) => object,
+        Err(err) => report(err, &sources),
+    };
+    if let Err(err) = write_file(&args[2], object.to_string().as_bytes()) {
+        report(err, &sources);
+    }
+}
+
+#[rustfmt::skip]
+fn link_main(mut args: Vec<String>) {
+    // オプション
+    let chunk_size = take_option(&mut args, "--chunk-size")
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(1);
+    let error_limit = take_option(&mut args, "--error-limit")
+        .and_then(|limit| limit.parse().ok())
+        .filter(|&limit| limit > 0);
+    let output = take_output(&mut args);
+    let extras = take_extras(&mut args);
+
+    if args.len() < 4 {
+        println!("Usage: {} link <data.hex> <inst.hex> <a.obj>... [--chunk-size <n>] [<output options>] [--error-limit <n>]", args[0]);
+        println!();
+        print!("{}", OUTPUT_OPTIONS);
+        return;
+    }
+
+    let objects = args[3..]
+        .iter()
+        .map(|path| {
+            let text = read_file(path);
+            match read_object(&text) {
+                Ok(object) => object,
+                Err(err) => report(err.into(), &SourceMap::single(path, &text)),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let options = output.options(chunk_size, error_limit);
+    let sources = load_sources(&objects, &FsLoader);
+    let (data, text) = match link_image(&objects, &options) {
+        Ok(result) => result,
+        Err(err) => report(err, &sources),
+    };
+    if let Err(err) = extras.write(&objects, &sources, &data, &text, &options) {
+        report(err, &sources);
+    }
+    let map = &options.memory_map;
+    let result = write_image(&args[1], data, &output, map.dmem, chunk_size)
+        .and_then(|()| write_image(&args[2], text, &output, map.imem, chunk_size));
+    if let Err(err) = result {
+        report(err, &sources);
+    }
+}
+
+#[rustfmt::skip]
+fn disassemble_main(mut args: Vec<String>) {
+    // オプション (末尾のこの byte はメモリの大きさまで埋めたものとして捨てる)
+    let fill = take_number(&mut args, "--fill").unwrap_or(0);
+
+    if args.len() < 2 {
+        println!("Usage: {} disassemble [path/to/inst.hex|.ihex|.srec] [<chunk_size>] [--fill <n>]", args[0]);
+        return;
+    }
+    if fill > 0xFF {
+        eprintln!("error: fill value {:#X} is not a byte", fill);
+        process::exit(1);
+    }
+
+    let hex = read_file(&args[1]);
+    let chunk_size = if args.len() >= 3 {
+        args[2].parse().unwrap_or(1)
+    } else {
+        1
+    };
+    let options = Options {
+        chunk_size,
+        fill: fill as u8,
+        ..Default::default()
+    };
+    match disassemble_with(&hex, &options) {
+        Ok(program) => print!("{}", program),
+        Err(err) => report(err, &SourceMap::single(&args[1], &hex)),
+    }
+}
+
+#[rustfmt::skip]
+fn run_main(mut args: Vec<String>) {
+    // オプション
+    let max_steps = take_option(&mut args, "--max-steps")
+        .and_then(|steps| steps.parse().ok())
+        .unwrap_or(10_000_000);
+
+    if args.len() < 2 {
+        println!("Usage: {} run [path/to/source] [--max-steps <n>]", args[0]);
+        println!("       {} run <data.hex> <inst.hex> [<chunk_size>] [--max-steps <n>]", args[0]);
+        return;
+    }
+
+    // ソースが 1 つだけ渡された場合はその場でアセンブルする
+    let mut sim = if args.len() == 2 {
+        let mut sources = SourceMap::new();
+        let (datas, insts) = match assemble_file(Path::new(&args[1]), &FsLoader, &mut sources, &Options::default()) {
+            Ok(result) => result,
+            Err(err) => report(err, &sources),
+        };
+        match Sim::from_hex(&datas, &insts, 1) {
+            Ok(sim) => sim,
+            Err(err) => report(err.into(), &sources),
+        }
+    } else {
+        let chunk_size = if args.len() >= 4 {
+            args[3].parse().unwrap_or(1)
+        } else {
+            1
+        };
+        let read = |path: &str| {
+            let hex = read_file(path);
+            match read_hex(&hex, chunk_size) {
+                Ok(bytes) => bytes,
+                Err(err) => report(err.into(), &SourceMap::single(path, &hex)),
+            }
+        };
+        Sim::new(read(&args[1]), read(&args[2]))
+    };
+
+    match sim.run(&mut StdIo, max_steps) {
+        Ok(Status::Halted) => eprintln!("halted at pc {:#X} after {} steps", sim.pc, sim.steps),
+        Ok(Status::Running) => eprintln!("stopped at pc {:#X} after {} steps (step limit)", sim.pc, sim.steps),
+        Err(trap) => {
+            eprintln!("error: {}", trap);
+            process::exit(1);
+        }
+    }
+}
+
+// ポート 0 は標準入出力 (1 文字ずつ), それ以外のポートへの出力は標準エラーに表示する
+struct StdIo;
+
+impl Io for StdIo {
+    fn input(&mut self, port: u32) -> u32 {
+        let mut byte = [0];
+        match port {
+            0 => match io::stdin().read(&mut byte) {
+                Ok(1) => byte[0] as u32,
+                _ => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    fn output(&mut self, port: u32, value: u32) {
+        match port {
+            0 => {
+                let mut stdout = io::stdout();
+                stdout.write_all(&[value as u8]).unwrap();
+                stdout.flush().unwrap();
+            }
+            _ => eprintln!("out[{}] = {:#X}", port, value),
+        }
+    }
+}
+
+const OUTPUT_OPTIONS: &str = "\
+Output options:
+    --format <hex|bin|ihex|srec|readmemh|sv|coe|mif>
+    --memory-map <file>                      base addresses and sizes of imem, dmem and other regions
+    --region <name>=<base>[:<size>]          a region of the memory map (imem, dmem or others)
+    --data-address <n>, --inst-address <n>   base address
+    --data-depth <n>, --inst-depth <n>       number of words (readmemh, sv, coe, mif)
+    --data-size <n|nw>, --inst-size <n|nw>   memory size in bytes, or words with `w`
+    --record-len <n>                         data bytes per record (ihex, srec)
+    --fill <n>                               byte filling the unused memory
+    --radix <bin|dec|hex>                    (coe, mif)
+    --listing <out.lst>
+    --symbols <out.map|out.json>
+    --scala <Rom.scala> [--scala-package <package>]
+";
+
+// data.hex, inst.hex の形式
+#[derive(Clone, Copy)]
+enum Format {
+    // chunk_size byte ごとの 16 進数の行
+    Hex,
+    // メモリイメージそのまま
+    Bin,
+    Ihex,
+    Srec,
+    // $readmemh
+    Readmemh,
+    // SystemVerilog の case 文の ROM モジュール (モジュール名はファイル名)
+    Sv,
+    Coe,
+    Mif,
+}
+
+// data.hex, inst.hex の書き方
+struct Output {
+    format: Format,
+    // Intel HEX, S-record の 1 レコードの byte 数
+    record_len: usize,
+    // COE, MIF の値の基数と, 空きを埋める byte
+    radix: Radix,
+    fill: u8,
+    // --memory-map のファイルと, それを上書きするオプション
+    memory_map: MemoryMap,
+    data: Memory,
+    inst: Memory,
+    regions: Vec<(String, Region)>,
+}
+
+// 各メモリのオプション
+struct Memory {
+    // 先頭のアドレス (byte)
+    address: Option<usize>,
+    // 語数
+    depth: Option<usize>,
+    // 大きさ (イメージをこの大きさまで埋め, 超えたらエラー)
+    size: Option<Size>,
+}
+
+enum Size {
+    Bytes(usize),
+    // chunk_size byte の語の数
+    Words(usize),
+}
+
+impl Memory {
+    // 語数から求めた大きさがあふれたらエラー
+    fn apply(&self, name: &str, region: &mut Region, chunk_size: usize) -> Result<(), Error> {
+        if let Some(address) = self.address {
+            region.base = address;
+        }
+        let bytes = |words: usize| {
+            words.checked_mul(chunk_size).ok_or_else(|| {
+                let reason = format!(
+                    "size of `{}` ({} words of {} bytes) overflows",
+                    name, words, chunk_size
+                );
+                Error::global(ErrorKind::InvalidMemoryMap(reason))
+            })
+        };
+        // 大きさの指定がなければ depth 語
+        match (&self.size, self.depth) {
+            (Some(Size::Bytes(size)), _) => region.size = Some(*size),
+            (Some(Size::Words(words)), _) => region.size = Some(bytes(*words)?),
+            (None, Some(depth)) => region.size = Some(bytes(depth)?),
+            (None, None) => {}
+        }
+        Ok(())
+    }
+}
+
+impl Output {
+    fn options(&self, chunk_size: usize, error_limit: Option<usize>) -> Options {
+        let mut memory_map = self.memory_map.clone();
+        let result = (self.data.apply("dmem", &mut memory_map.dmem, chunk_size))
+            .and_then(|()| self.inst.apply("imem", &mut memory_map.imem, chunk_size));
+        if let Err(err) = result {
+            report(Errors::from(err).into(), &SourceMap::new());
+        }
+        for (name, region) in &self.regions {
+            *memory_map.get_mut(name) = *region;
+        }
+        Options {
+            chunk_size,
+            error_limit,
+            memory_map,
+            fill: self.fill,
+        }
+    }
+}
+
+// --format, --record-len, --radix, --fill, --memory-map, --region, --{data,inst}-{address,depth,size}
+// を取り除いて返す (既定は hex)
+fn take_output(args: &mut Vec<String>) -> Output {
+    let format = match take_option(args, "--format").as_deref() {
+        None | Some("hex") => Format::Hex,
+        Some("bin") => Format::Bin,
+        Some("ihex") => Format::Ihex,
+        Some("srec") => Format::Srec,
+        Some("readmemh") => Format::Readmemh,
+        Some("sv") => Format::Sv,
+        Some("coe") => Format::Coe,
+        Some("mif") => Format::Mif,
+        Some(format) => {
+            eprintln!(
+                "error: unknown output format `{}` (expected hex, bin, ihex, srec, readmemh, sv, coe or mif)",
+                format
+            );
+            process::exit(1);
+        }
+    };
+    let radix = match take_option(args, "--radix").as_deref() {
+        None | Some("hex") | Some("16") => Radix::Hex,
+        Some("dec") | Some("10") => Radix::Dec,
+        Some("bin") | Some("2") => Radix::Bin,
+        Some(radix) => {
+            eprintln!(
+                "error: unknown radix `{}` (expected bin, dec or hex)",
+                radix
+            );
+            process::exit(1);
+        }
+    };
+    let fill = take_number(args, "--fill").unwrap_or(0);
+    if fill > 0xFF {
+        eprintln!("error: fill value {:#X} is not a byte", fill);
+        process::exit(1);
+    }
+    let memory_map = match take_option(args, "--memory-map") {
+        Some(path) => {
+            let text = read_file(&path);
+            match read_memory_map(&text) {
+                Ok(map) => map,
+                Err(err) => report(err.into(), &SourceMap::single(&path, &text)),
+            }
+        }
+        None => MemoryMap::default(),
+    };
+    let mut regions = Vec::new();
+    while let Some(region) = take_option(args, "--region") {
+        regions.push(parse_region(&region));
+    }
+    Output {
+        format,
+        record_len: take_number(args, "--record-len").unwrap_or(16),
+        radix,
+        fill: fill as u8,
+        memory_map,
+        data: Memory {
+            address: take_number(args, "--data-address"),
+            depth: take_number(args, "--data-depth"),
+            size: take_size(args, "--data-size"),
+        },
+        inst: Memory {
+            address: take_number(args, "--inst-address"),
+            depth: take_number(args, "--inst-depth"),
+            size: take_size(args, "--inst-size"),
+        },
+        regions,
+    }
+}
+
+// name=base[:size]
+fn parse_region(text: &str) -> (String, Region) {
+    let Some((name, rest)) = text.split_once('=') else {
+        eprintln!(
+            "error: invalid region `{}` (expected name=base[:size])",
+            text
+        );
+        process::exit(1);
+    };
+    let (base, size) = match rest.split_once(':') {
+        Some((base, size)) => (base, Some(size)),
+        None => (rest, None),
+    };
+    let region = Region {
+        base: parse_number(base, "--region"),
+        size: size.map(|size| parse_number(size, "--region")),
+    };
+    (name.to_string(), region)
+}
+
+// region は memory_map のもの (イメージの先頭がその先頭アドレス)
+fn write_image(
+    path: &str,
+    bytes: Vec<u8>,
+    output: &Output,
+    region: Region,
+    chunk_size: usize,
+) -> anyhow::Result<()> {
+    let address = region.base;
+    let depth = region.size.map(|size| size.div_ceil(chunk_size));
+    let text = match output.format {
+        Format::Hex => to_hex(bytes, chunk_size),
+        Format::Bin => {
+            write_file(path, &bytes)?;
+            return Ok(());
+        }
+        Format::Ihex => ihex::write(&bytes, address, output.record_len)?,
+        Format::Srec => srec::write(&bytes, address, output.record_len)?,
+        Format::Readmemh => verilog::readmemh(bytes, chunk_size, address, depth)?,
+        Format::Sv => verilog::rom_module(bytes, chunk_size, depth, &module_name(path))?,
+        Format::Coe => coe::write(bytes, chunk_size, output.radix, depth, output.fill)?,
+        Format::Mif => mif::write(bytes, chunk_size, output.radix, depth, output.fill)?,
+    };
+    write_file(path, text.as_bytes())
+}
+
+// ファイル名から識別子に使えない文字を _ にしたもの
+fn module_name(path: &str) -> String {
+    let stem = Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    match name.chars().next() {
+        Some(c) if !c.is_ascii_digit() => name,
+        _ => format!("rom_{}", name),
+    }
+}
+
+// リスティングなど, オブジェクトから作る出力
+struct Extras {
+    listing: Option<String>,
+    // 拡張子が .json なら JSON, それ以外はテキスト
+    symbols: Option<String>,
+    // Chisel の ROM (object 名はファイル名)
+    scala: Option<String>,
+    scala_package: Option<String>,
+}
+
+fn take_extras(args: &mut Vec<String>) -> Extras {
+    Extras {
+        listing: take_option(args, "--listing"),
+        symbols: take_option(args, "--symbols"),
+        scala: take_option(args, "--scala"),
+        scala_package: take_option(args, "--scala-package"),
+    }
+}
+
+impl Extras {
+    fn is_empty(&self) -> bool {
+        self.listing.is_none() && self.symbols.is_none() && self.scala.is_none()
+    }
+
+    fn write(
+        &self,
+        objects: &[Object],
+        sources: &SourceMap,
+        data: &[u8],
+        text: &[u8],
+        options: &Options,
+    ) -> anyhow::Result<()> {
+        let memory_map = &options.memory_map;
+        if let Some(path) = &self.listing {
+            let listing = listing(objects, sources, memory_map)?;
+            write_file(path, listing.as_bytes())?;
+        }
+        let map = SymbolMap::new(objects, memory_map);
+        if let Some(path) = &self.symbols {
+            let text = match Path::new(path).extension() {
+                Some(ext) if ext == "json" => map.to_json(),
+                _ => map.to_text(),
+            };
+            write_file(path, text.as_bytes())?;
+        }
+        if let Some(path) = &self.scala {
+            let name = module_name(path);
+            let package = self.scala_package.as_deref();
+            let scala = chisel::write(data, text, options.chunk_size, &map, &name, package)?;
+            write_file(path, scala.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+// "--name value" を args から取り除いて value を返す
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let pos = args.iter().position(|arg| arg == name)?;
+    if pos + 1 >= args.len() {
+        return None;
+    }
+    let value = args.remove(pos + 1);
+    args.remove(pos);
+    Some(value)
+}
+
+// 10 進数または 0x で始まる 16 進数のオプション
+fn take_number(args: &mut Vec<String>, name: &str) -> Option<usize> {
+    let value = take_option(args, name)?;
+    Some(parse_number(&value, name))
+}
+
+// byte 数, または w を付けた語数 (256w)
+fn take_size(args: &mut Vec<String>, name: &str) -> Option<Size> {
+    let value = take_option(args, name)?;
+    match value.strip_suffix('w') {
+        Some(words) => Some(Size::Words(parse_number(words, name))),
+        None => Some(Size::Bytes(parse_number(&value, name))),
+    }
+}
+
+fn parse_number(value: &str, name: &str) -> usize {
+    match memory_map::parse_number(value) {
+        Ok(number) => number,
+        Err(reason) => {
+            eprintln!("error: {} for {}", reason, name);
+            process::exit(1);
+        }
+    }
+}
+
+// 読めなければ, ソースと同じ形でエラーを表示して終了する
+fn read_file(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => {
+            let kind = ErrorKind::CannotRead {
+                path: path.to_string(),
+                reason: err.to_string(),
+            };
+            report(Errors::from(Error::global(kind)).into(), &SourceMap::new())
+        }
+    }
+}
+
+// 書けなければ, そのファイルの名前を添えたエラーにする
+fn write_file(path: &str, bytes: &[u8]) -> anyhow::Result<()> {
+    fs::write(path, bytes).map_err(|err| anyhow::anyhow!("Cannot write {}: {}", path, err))
+}
+
+fn report(err: anyhow::Error, sources: &SourceMap) -> ! {
+    match err.downcast_ref::<Errors>() {
+        Some(errs) => eprint!("{}", errs.render(sources)),
+        None => eprintln!("error: {}", err),
+    }
+    process::exit(1);
 }